@@ -0,0 +1,123 @@
+// Compare reassembling a large message the way the server did before, collecting the fragments in a Vec,
+// with writing each fragment in place, both up to the parsed message as the server needs it
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use common_structs::message::Message;
+use criterion::{
+    black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput,
+};
+use rusty_drones_servers::ReassemblyBuffer;
+use wg_2024::packet::Fragment;
+
+const MESSAGE_SIZES: [usize; 3] = [1 << 20, 4 << 20, 16 << 20];
+
+/// Allocator that counts the bytes allocated, to compare the memory used while reassembling
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static IN_USE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        IN_USE.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn record_alloc(size: usize) {
+    ALLOCATED.fetch_add(size, Ordering::Relaxed);
+    let in_use = IN_USE.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK.fetch_max(in_use, Ordering::Relaxed);
+}
+
+/// Bytes allocated in total and at most at once while running a function
+fn allocations<T>(f: impl FnOnce() -> T) -> (usize, usize) {
+    let allocated = ALLOCATED.load(Ordering::Relaxed);
+    let in_use = IN_USE.load(Ordering::Relaxed);
+    PEAK.store(in_use, Ordering::Relaxed);
+    drop(black_box(f()));
+    (
+        ALLOCATED.load(Ordering::Relaxed) - allocated,
+        PEAK.load(Ordering::Relaxed) - in_use,
+    )
+}
+
+fn media_fragments(size: usize) -> Vec<Fragment> {
+    Message::RespMedia(vec![42; size]).into_fragments()
+}
+
+/// Collect the fragments as they arrive and parse them once all are there
+fn fragment_vec(fragments: Vec<Fragment>) -> Message {
+    let mut pending = Vec::with_capacity(fragments.len());
+    for fragment in fragments {
+        pending.push(fragment);
+    }
+    Message::from_fragments(pending).expect("Fragments form a message")
+}
+
+/// Write the fragments in place as they arrive and parse them once all are there
+fn byte_buffer(fragments: Vec<Fragment>) -> Message {
+    let mut buffer = ReassemblyBuffer::new(fragments.len() as u64).expect("Buffer fits in memory");
+    for fragment in fragments {
+        buffer.insert(&fragment);
+    }
+    Message::from_fragments(buffer.into_fragments()).expect("Fragments form a message")
+}
+
+fn reassembly(c: &mut Criterion) {
+    let mut group = c.benchmark_group("reassembly");
+    group.sample_size(10);
+
+    for size in MESSAGE_SIZES {
+        let fragments = media_fragments(size);
+
+        for (name, reassemble) in [
+            ("fragment_vec", fragment_vec as fn(Vec<Fragment>) -> Message),
+            ("byte_buffer", byte_buffer),
+        ] {
+            // The received fragments are allocated before, only the reassembly itself is counted
+            let received = fragments.clone();
+            let (allocated, peak) = allocations(|| reassemble(received));
+            eprintln!(
+                "reassembly/{}/{}: allocated {} bytes, at most {} bytes at once",
+                name, size, allocated, peak
+            );
+
+            group.throughput(Throughput::Bytes(size as u64));
+            group.bench_with_input(BenchmarkId::new(name, size), &fragments, |b, fragments| {
+                b.iter_batched(
+                    || fragments.clone(),
+                    |fragments| black_box(reassemble(fragments)),
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, reassembly);
+criterion_main!(benches);
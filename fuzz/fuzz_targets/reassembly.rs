@@ -0,0 +1,86 @@
+#![no_main]
+// Fuzzing of the reassembly of fragments by the server
+// Run with `cargo fuzz run reassembly` from the root of the repository
+
+use std::collections::HashMap;
+
+use common_structs::leaf::{LeafCommand, LeafEvent};
+use crossbeam_channel::{unbounded, Sender};
+use libfuzzer_sys::fuzz_target;
+use rusty_drones_servers::{text::TextServer, Server, ServerConfig};
+use wg_2024::{
+    network::{NodeId, SourceRoutingHeader},
+    packet::{Fragment, Packet, FRAGMENT_DSIZE},
+};
+
+const SERVER: NodeId = 1;
+/// Session (1) + hop index (1) + hop count (1) + hops (4) + fragment index (8) + total (8) + length (1)
+const HEADER_LENGTH: usize = 24;
+
+/// Read the next fragment from the input, the data is as long as the input allows
+fn next_packet(input: &mut &[u8]) -> Option<Packet> {
+    if input.len() < HEADER_LENGTH {
+        return None;
+    }
+    let (header, rest) = input.split_at(HEADER_LENGTH);
+    let u64_at = |start: usize| {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&header[start..start + 8]);
+        u64::from_le_bytes(bytes)
+    };
+
+    let hop_count = (header[2] % 5) as usize;
+    let routing = SourceRoutingHeader {
+        hop_index: header[1] as usize,
+        hops: header[3..3 + hop_count].to_vec(),
+    };
+    let length = header[23];
+
+    let data_length = (length as usize).min(FRAGMENT_DSIZE).min(rest.len());
+    let mut data = [0; FRAGMENT_DSIZE];
+    data[..data_length].copy_from_slice(&rest[..data_length]);
+    *input = &rest[data_length..];
+
+    Some(Packet::new_fragment(
+        routing,
+        header[0] as u64 % 4,
+        Fragment {
+            fragment_index: u64_at(7),
+            total_n_fragments: u64_at(15),
+            length,
+            data,
+        },
+    ))
+}
+
+fuzz_target!(|data: &[u8]| {
+    let (controller_send, _controller_events) = unbounded::<LeafEvent>();
+    let (_command_send, controller_recv) = unbounded::<LeafCommand>();
+    let (packet_send, packet_recv) = unbounded::<Packet>();
+
+    let mut neighbors = Vec::new();
+    let mut neighbor_send = HashMap::<NodeId, Sender<Packet>>::new();
+    for neighbor_id in [0, 2, 3] {
+        let (send, recv) = unbounded::<Packet>();
+        neighbor_send.insert(neighbor_id, send);
+        neighbors.push(recv);
+    }
+
+    let mut server = Server::create(
+        SERVER,
+        controller_send,
+        controller_recv,
+        packet_recv,
+        neighbor_send,
+        TextServer::new(HashMap::new()),
+        ServerConfig::default(),
+    );
+
+    let mut input = data;
+    while let Some(packet) = next_packet(&mut input) {
+        if packet_send.send(packet).is_err() {
+            return;
+        }
+        server.poll();
+    }
+});
@@ -0,0 +1,107 @@
+use std::{
+    error::Error,
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use rusty_drones_servers::{RetransmitPolicy, RoutingStrategy, ServerConfig, ShortcutPolicy};
+use serde::Deserialize;
+use wg_2024::network::NodeId;
+
+/// Protocol the server speaks
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerKind {
+    Text,
+    Media,
+    Chat,
+}
+
+/// Neighbor reachable through a socket
+#[derive(Debug, Clone, Deserialize)]
+pub struct NeighborConfig {
+    pub id: NodeId,
+    pub address: SocketAddr,
+}
+
+/// Tuning options, anything left out keeps its default value
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tuning {
+    pub tick_interval_ms: Option<u64>,
+    pub drain_timeout_ms: Option<u64>,
+    pub min_flood_interval_ms: Option<u64>,
+    pub ack_timeout_ms: Option<u64>,
+    pub backoff: Option<u32>,
+    pub max_retries: Option<u32>,
+    pub routing: Option<RoutingStrategy>,
+}
+
+/// Configuration of a server running outside of the simulation
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NodeConfig {
+    pub id: NodeId,
+    pub kind: ServerKind,
+    /// Address of the socket the packets are received on
+    pub address: SocketAddr,
+    /// Directory with the files (text, a tree linked by relative path) or media (media) to serve, the built-in content is used if not set
+    pub content: Option<PathBuf>,
+    /// Handle requests on a pool of this many worker threads (text and media only)
+    pub workers: Option<usize>,
+    #[serde(default)]
+    pub neighbors: Vec<NeighborConfig>,
+    #[serde(default)]
+    pub tuning: Tuning,
+}
+
+impl NodeConfig {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        let config: NodeConfig = toml::from_str(&text)?;
+        // Reject values the server cannot run with, instead of running with the defaults
+        if let Err(e) = config.server_config().validate() {
+            return Err(format!("Invalid tuning, {}", e).into());
+        }
+        Ok(config)
+    }
+
+    /// Server configuration with the tuning options applied
+    pub fn server_config(&self) -> ServerConfig {
+        let tuning = &self.tuning;
+        let mut config = ServerConfig::default()
+            // There is no simulation controller to take shortcuts through
+            .with_shortcut_policy(ShortcutPolicy {
+                acks: false,
+                nacks: false,
+                flood_responses: false,
+            });
+
+        if let Some(ms) = tuning.tick_interval_ms {
+            config = config.with_tick_interval(Duration::from_millis(ms));
+        }
+        if let Some(ms) = tuning.drain_timeout_ms {
+            config = config.with_drain_timeout(Duration::from_millis(ms));
+        }
+        if let Some(ms) = tuning.min_flood_interval_ms {
+            config = config.with_min_flood_interval(Duration::from_millis(ms));
+        }
+
+        let default_retransmit = RetransmitPolicy::default();
+        config = config.with_retransmit_policy(RetransmitPolicy {
+            ack_timeout: tuning
+                .ack_timeout_ms
+                .map(Duration::from_millis)
+                .unwrap_or(default_retransmit.ack_timeout),
+            backoff: tuning.backoff.unwrap_or(default_retransmit.backoff),
+            max_retries: tuning.max_retries.unwrap_or(default_retransmit.max_retries),
+        });
+
+        if let Some(routing) = tuning.routing {
+            config = config.with_routing_strategy(routing);
+        }
+        config
+    }
+}
@@ -0,0 +1,159 @@
+//! Run a single server outside of the simulation, exchanging packets with its neighbors over UDP
+//!
+//! Usage: `rusty-drones-server <config.toml>` (built with `--features bin`), for example:
+//!
+//! ```toml
+//! id = 10
+//! kind = "text"
+//! address = "127.0.0.1:9010"
+//! content = "content/text"
+//! workers = 4
+//!
+//! [[neighbors]]
+//! id = 1
+//! address = "127.0.0.1:9001"
+//!
+//! [tuning]
+//! ack_timeout_ms = 250
+//! routing = "fewest_hops"
+//! ```
+
+mod config;
+
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    error::Error,
+    net::UdpSocket,
+    path::Path,
+    process::ExitCode,
+    sync::Arc,
+    thread,
+};
+
+use common_structs::leaf::{LeafCommand, LeafEvent};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use log::{info, warn};
+use rusty_drones_servers::{
+    chat, media, spawn_udp_receiver, text, Server, ServerConfig, ServerProtocol, UdpTransport,
+    WorkerPool,
+};
+use wg_2024::{network::NodeId, packet::Packet};
+
+use config::{NodeConfig, ServerKind};
+
+/// Everything the server is connected to
+struct Links {
+    id: NodeId,
+    controller_send: Sender<LeafEvent>,
+    controller_recv: Receiver<LeafCommand>,
+    packet_recv: Receiver<Packet>,
+    packet_send: HashMap<NodeId, UdpTransport>,
+}
+
+fn serve<T: ServerProtocol>(links: Links, protocol: T, config: ServerConfig) {
+    let mut server = Server::create(
+        links.id,
+        links.controller_send,
+        links.controller_recv,
+        links.packet_recv,
+        links.packet_send,
+        protocol,
+        config,
+    );
+    server.run();
+}
+
+fn run(config: NodeConfig) -> Result<(), Box<dyn Error>> {
+    let socket = Arc::new(UdpSocket::bind(config.address)?);
+    info!("Server {} listening on {}.", config.id, config.address);
+
+    let (packet_forward, packet_recv) = unbounded::<Packet>();
+    spawn_udp_receiver(socket.clone(), packet_forward);
+    let packet_send = config
+        .neighbors
+        .iter()
+        .map(|neighbor| {
+            (
+                neighbor.id,
+                UdpTransport::new(socket.clone(), neighbor.address),
+            )
+        })
+        .collect();
+
+    // Nobody watches the events outside of the simulation
+    let (controller_send, controller_events) = unbounded::<LeafEvent>();
+    thread::spawn(move || for _ in controller_events {});
+    // Commands are never sent, but the channel has to stay open while the server runs
+    let (_controller_commands, controller_recv) = unbounded::<LeafCommand>();
+
+    let links = Links {
+        id: config.id,
+        controller_send,
+        controller_recv,
+        packet_recv,
+        packet_send,
+    };
+    let server_config = config.server_config();
+    match config.kind {
+        ServerKind::Text => {
+            let files = match &config.content {
+                Some(dir) => {
+                    let loaded = text::load_files(dir)?;
+                    if !loaded.errors.is_empty() {
+                        warn!(
+                            "WARNING: {} files could not be loaded.",
+                            loaded.errors.len()
+                        );
+                    }
+                    loaded.files
+                }
+                None => text::default_files(),
+            };
+            info!("Serving {} files.", files.len());
+            let protocol = text::TextServer::new(files);
+            match config.workers {
+                Some(workers) => serve(links, WorkerPool::new(protocol, workers), server_config),
+                None => serve(links, protocol, server_config),
+            }
+        }
+        ServerKind::Media => {
+            let media = match &config.content {
+                Some(dir) => media::load_media(dir)?,
+                None => media::default_media(),
+            };
+            info!("Serving {} media.", media.len());
+            let protocol = media::MediaServer::new(media);
+            match config.workers {
+                Some(workers) => serve(links, WorkerPool::new(protocol, workers), server_config),
+                None => serve(links, protocol, server_config),
+            }
+        }
+        ServerKind::Chat => serve(links, chat::ChatServer::new(HashSet::new()), server_config),
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    env_logger::init();
+
+    let Some(path) = env::args().nth(1) else {
+        eprintln!("Usage: rusty-drones-server <config.toml>");
+        return ExitCode::FAILURE;
+    };
+    let config = match NodeConfig::load(Path::new(&path)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Could not load config {}. {}", path, e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match run(config) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Server stopped. {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
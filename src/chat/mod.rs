@@ -5,9 +5,10 @@ use common_structs::{
     message::{Message, ServerType},
 };
 use crossbeam_channel::{Receiver, Sender};
+use log::warn;
 use wg_2024::{network::NodeId, packet::Packet};
 
-use crate::server::{Server, ServerProtocol, ServerSenders};
+use crate::server::{Server, ServerConfig, ServerProtocol, ServerSenders};
 
 pub struct ChatServer {
     connected_clients: HashSet<NodeId>,
@@ -17,6 +18,20 @@ impl ChatServer {
     pub fn new(connected_clients: HashSet<NodeId>) -> Self {
         Self { connected_clients }
     }
+
+    /// Send a message, the chat protocol has no way to report failures to the clients
+    fn send(
+        server: NodeId,
+        senders: &mut ServerSenders,
+        to: NodeId,
+        message: Message,
+        session_id: Option<u64>,
+    ) {
+        if let Err(e) = Server::<ChatServer>::send_message(server, senders, to, message, session_id)
+        {
+            warn!("WARNING: Could not send message to {}. {}", to, e);
+        }
+    }
 }
 
 impl ServerProtocol for ChatServer {
@@ -30,7 +45,7 @@ impl ServerProtocol for ChatServer {
     ) {
         match message {
             Message::ReqServerType => {
-                Server::<ChatServer>::send_message(
+                Self::send(
                     server,
                     senders,
                     from,
@@ -43,7 +58,7 @@ impl ServerProtocol for ChatServer {
                 self.connected_clients.insert(from);
 
                 for client in self.connected_clients.iter() {
-                    Server::<ChatServer>::send_message(
+                    Self::send(
                         server,
                         senders,
                         *client,
@@ -56,7 +71,7 @@ impl ServerProtocol for ChatServer {
             }
             Message::ReqChatClients => {
                 // List known clients
-                Server::<ChatServer>::send_message(
+                Self::send(
                     server,
                     senders,
                     from,
@@ -67,7 +82,7 @@ impl ServerProtocol for ChatServer {
             Message::ReqChatSend { to, chat_msg } => {
                 if !self.connected_clients.contains(&to) {
                     // Receiver client has not registered themselves
-                    Server::<ChatServer>::send_message(
+                    Self::send(
                         server,
                         senders,
                         from,
@@ -78,7 +93,7 @@ impl ServerProtocol for ChatServer {
                 }
 
                 // Forward message to known client
-                Server::<ChatServer>::send_message(
+                Self::send(
                     server,
                     senders,
                     to,
@@ -88,7 +103,7 @@ impl ServerProtocol for ChatServer {
             }
             _ => {
                 // Default response
-                Server::<ChatServer>::send_message(
+                Self::send(
                     server,
                     senders,
                     from,
@@ -118,6 +133,7 @@ impl Leaf for Server<ChatServer> {
             packet_recv,
             packet_send,
             ChatServer::new(HashSet::new()),
+            ServerConfig::default(),
         )
     }
 
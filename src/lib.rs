@@ -1,9 +1,24 @@
-mod chat;
-mod media;
+pub mod chat;
+pub mod media;
 mod server;
+/// Simulated network to test servers in, not part of the regular API
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
 mod test;
-mod text;
+pub mod text;
 
 pub type ChatServer = server::Server<chat::ChatServer>;
 pub type MediaServer = server::Server<media::MediaServer>;
 pub type TextServer = server::Server<text::TextServer>;
+/// Media server handling requests on a pool of worker threads
+pub type PooledMediaServer = server::Server<server::WorkerPool<media::MediaServer>>;
+/// Text server handling requests on a pool of worker threads
+pub type PooledTextServer = server::Server<server::WorkerPool<text::TextServer>>;
+
+pub use server::codec;
+pub use server::{
+    replay, spawn_udp_receiver, Capture, CaptureReader, Clock, ConfigError, HistoryLimits,
+    OutboundLimits, PacketTransport, ReassemblyBuffer, ReassemblyLimits, RetransmitPolicy,
+    RoutingStrategy, Server, ServerConfig, ServerProtocol, ServerReport, ServerStats,
+    ShortcutPolicy, UdpTransport, VirtualClock, WorkerPool,
+};
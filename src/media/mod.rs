@@ -1,6 +1,9 @@
 use std::{
     collections::HashMap,
+    fs,
     hash::{DefaultHasher, Hash, Hasher},
+    io,
+    path::Path,
 };
 
 use common_structs::{
@@ -8,9 +11,15 @@ use common_structs::{
     message::{Link, Media, Message, ServerType},
 };
 use crossbeam_channel::{Receiver, Sender};
+use log::warn;
 use wg_2024::{network::NodeId, packet::Packet};
 
-use crate::server::{Server, ServerProtocol, ServerSenders};
+use crate::server::{
+    ConcurrentProtocol, Reply, Server, ServerConfig, ServerProtocol, ServerSenders, WorkerPool,
+};
+
+/// Amount of threads handling requests when running on a worker pool
+const WORKER_COUNT: usize = 4;
 
 pub struct MediaServer {
     uuid: u64,
@@ -19,10 +28,40 @@ pub struct MediaServer {
 
 impl MediaServer {
     pub fn new(media_map: HashMap<Link, Media>) -> Self {
-        let mut s = DefaultHasher::new();
-        "SamuelMediaServer".hash(&mut s);
-        let uuid = s.finish();
-        Self { uuid, media_map }
+        Self {
+            uuid: media_uuid(),
+            media_map,
+        }
+    }
+}
+
+/// Uuid every media server reports, text files refer to their media by it
+pub fn media_uuid() -> u64 {
+    let mut s = DefaultHasher::new();
+    "SamuelMediaServer".hash(&mut s);
+    s.finish()
+}
+
+impl ConcurrentProtocol for MediaServer {
+    fn handle(
+        &self,
+        _server: NodeId,
+        from: NodeId,
+        message: Message,
+        session_id: u64,
+    ) -> Vec<Reply> {
+        let response = match message {
+            Message::ReqServerType => Message::RespServerType(ServerType::Media(self.uuid)),
+            Message::ReqMedia(id) => match self.media_map.get(&id) {
+                // Media is present in this server
+                Some(media) => Message::RespMedia(media.clone()),
+                // Media with that id is not known
+                None => Message::ErrNotFound,
+            },
+            // Default response
+            _ => Message::ErrUnsupportedRequestType,
+        };
+        vec![Reply::response(from, response, session_id)]
     }
 }
 
@@ -35,48 +74,54 @@ impl ServerProtocol for MediaServer {
         message: Message,
         session_id: u64,
     ) {
-        match message {
-            Message::ReqServerType => {
-                Server::<MediaServer>::send_message(
-                    server,
-                    senders,
-                    from,
-                    Message::RespServerType(ServerType::Media(self.uuid)),
-                    Some(session_id),
-                );
+        for reply in self.handle(server, from, message, session_id) {
+            if let Err(e) = Server::<MediaServer>::send_message(
+                server,
+                senders,
+                reply.to,
+                reply.message,
+                reply.session_id,
+            ) {
+                warn!("WARNING: Could not send response to {}. {}", reply.to, e);
             }
-            Message::ReqMedia(id) => {
-                match self.media_map.get(&id) {
-                    // Media is present in this server
-                    Some(media) => Server::<MediaServer>::send_message(
-                        server,
-                        senders,
-                        from,
-                        Message::RespMedia(media.clone()),
-                        Some(session_id),
-                    ),
-                    // Media with that id is not known
-                    None => Server::<MediaServer>::send_message(
-                        server,
-                        senders,
-                        from,
-                        Message::ErrNotFound,
-                        Some(session_id),
-                    ),
-                };
-            }
-            _ => {
-                // Default response
-                Server::<MediaServer>::send_message(
-                    server,
-                    senders,
-                    from,
-                    Message::ErrUnsupportedRequestType,
-                    Some(session_id),
-                );
+        }
+    }
+}
+
+/// Media available in the network
+pub fn default_media() -> HashMap<Link, Media> {
+    let mut media_map = HashMap::new();
+    media_map.insert(
+        String::from("chicken.jpeg"),
+        Vec::from(include_bytes!("chicken.jpeg")),
+    );
+    media_map
+}
+
+/// Media in a directory, each file is available by its name
+pub fn load_media(dir: &Path) -> io::Result<HashMap<Link, Media>> {
+    let mut media_map = HashMap::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            warn!(
+                "WARNING: Skipping media with invalid name {}.",
+                path.display()
+            );
+            continue;
+        };
+
+        match fs::read(&path) {
+            Ok(media) => {
+                media_map.insert(String::from(name), media);
             }
+            Err(e) => warn!("WARNING: Skipping media {}. {}", path.display(), e),
         }
     }
+    Ok(media_map)
 }
 
 impl Leaf for Server<MediaServer> {
@@ -90,19 +135,41 @@ impl Leaf for Server<MediaServer> {
     where
         Self: Sized,
     {
-        // Media available in the network
-        let mut media_map = HashMap::new();
-        media_map.insert(
-            String::from("chicken.jpeg"),
-            Vec::from(include_bytes!("chicken.jpeg")),
-        );
         Server::create(
             id,
             controller_send,
             controller_recv,
             packet_recv,
             packet_send,
-            MediaServer::new(media_map),
+            MediaServer::new(default_media()),
+            ServerConfig::default(),
+        )
+    }
+
+    fn run(&mut self) {
+        self.run();
+    }
+}
+
+impl Leaf for Server<WorkerPool<MediaServer>> {
+    fn new(
+        id: NodeId,
+        controller_send: Sender<LeafEvent>,
+        controller_recv: Receiver<LeafCommand>,
+        packet_recv: Receiver<Packet>,
+        packet_send: HashMap<NodeId, Sender<Packet>>,
+    ) -> Self
+    where
+        Self: Sized,
+    {
+        Server::create(
+            id,
+            controller_send,
+            controller_recv,
+            packet_recv,
+            packet_send,
+            WorkerPool::new(MediaServer::new(default_media()), WORKER_COUNT),
+            ServerConfig::default(),
         )
     }
 
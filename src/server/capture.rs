@@ -0,0 +1,463 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use common_structs::{
+    leaf::{LeafCommand, LeafEvent},
+    types::Session,
+};
+use crossbeam_channel::{unbounded, Receiver, SendError};
+use log::warn;
+use wg_2024::{network::NodeId, packet::Packet};
+
+use super::{
+    codec::{self, CodecError},
+    Clock, PacketTransport, Server, ServerConfig, ServerProtocol, VirtualClock,
+};
+
+/// Start of every capture file
+const MAGIC: &[u8; 4] = b"RDCP";
+/// Version of the capture format, stored after the magic
+const CAPTURE_VERSION: u8 = 2;
+/// Smallest step of the virtual clock when replaying, so a timer that stays due cannot stall the replay
+const MIN_REPLAY_STEP: Duration = Duration::from_millis(1);
+
+const TAG_START: u8 = 0;
+const TAG_DISCOVER: u8 = 1;
+const TAG_RECEIVED: u8 = 2;
+const TAG_SENT: u8 = 3;
+const TAG_ADD_SENDER: u8 = 4;
+const TAG_REMOVE_SENDER: u8 = 5;
+const TAG_KILL: u8 = 6;
+
+/// Something that happened to a server
+#[derive(Debug, Clone)]
+pub enum CaptureRecord {
+    /// State of the server when the capture started
+    Start {
+        server: NodeId,
+        neighbors: Vec<NodeId>,
+        session_id: Session,
+        flood_id: u64,
+    },
+    /// The server started a flood to discover the network (e.g. when its run loop started)
+    Discover,
+    Received(Packet),
+    Sent {
+        neighbor: NodeId,
+        packet: Packet,
+    },
+    AddSender(NodeId),
+    RemoveSender(NodeId),
+    Kill,
+}
+
+/// Record together with the time since the capture started
+#[derive(Debug, Clone)]
+pub struct CaptureEntry {
+    pub at: Duration,
+    pub record: CaptureRecord,
+}
+
+/// Records the traffic of a server to a file, shared with the transports of the server
+#[derive(Clone)]
+pub struct Capture {
+    sink: Arc<Mutex<Box<dyn Write + Send>>>,
+    /// Clock of the server the capture is attached to, records are timed by it
+    clock: Clock,
+    started: Instant,
+}
+
+impl Capture {
+    pub fn new<W: Write + Send + 'static>(mut writer: W) -> io::Result<Self> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[CAPTURE_VERSION])?;
+        let clock = Clock::default();
+        Ok(Capture {
+            sink: Arc::new(Mutex::new(Box::new(writer))),
+            started: clock.now(),
+            clock,
+        })
+    }
+
+    /// Time the records by the given clock, starting now
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.started = clock.now();
+        self.clock = clock;
+        self
+    }
+
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn start(&self, server: NodeId, neighbors: &[NodeId], session_id: Session, flood_id: u64) {
+        // Every node id can be a neighbor, so the count does not fit in a byte
+        let mut body = vec![server];
+        body.extend_from_slice(&(neighbors.len() as u16).to_be_bytes());
+        body.extend_from_slice(neighbors);
+        body.extend_from_slice(&session_id.to_be_bytes());
+        body.extend_from_slice(&flood_id.to_be_bytes());
+        self.write(TAG_START, &body);
+    }
+
+    pub fn discover(&self) {
+        self.write(TAG_DISCOVER, &[]);
+    }
+
+    pub fn received(&self, packet: &Packet) {
+        match codec::encode(packet) {
+            Ok(frame) => self.write(TAG_RECEIVED, &frame),
+            Err(e) => warn!("WARNING: Could not capture received packet. {}", e),
+        }
+    }
+
+    pub fn sent(&self, neighbor: NodeId, packet: &Packet) {
+        match codec::encode(packet) {
+            Ok(frame) => {
+                let mut body = vec![neighbor];
+                body.extend_from_slice(&frame);
+                self.write(TAG_SENT, &body);
+            }
+            Err(e) => warn!("WARNING: Could not capture sent packet. {}", e),
+        }
+    }
+
+    pub fn command(&self, command: &LeafCommand) {
+        match command {
+            LeafCommand::AddSender(node_id, _) => self.write(TAG_ADD_SENDER, &[*node_id]),
+            LeafCommand::RemoveSender(node_id) => self.write(TAG_REMOVE_SENDER, &[*node_id]),
+            LeafCommand::Kill => self.write(TAG_KILL, &[]),
+        }
+    }
+
+    pub fn flush(&self) {
+        if let Ok(mut sink) = self.sink.lock() {
+            if let Err(e) = sink.flush() {
+                warn!("WARNING: Could not flush capture. {}", e);
+            }
+        }
+    }
+
+    /// Write a single record, the capture is left out rather than failing the server
+    fn write(&self, tag: u8, body: &[u8]) {
+        let micros = self
+            .clock
+            .now()
+            .saturating_duration_since(self.started)
+            .as_micros() as u64;
+        let mut entry = Vec::with_capacity(9 + body.len());
+        entry.extend_from_slice(&micros.to_be_bytes());
+        entry.push(tag);
+        entry.extend_from_slice(body);
+
+        match self.sink.lock() {
+            Ok(mut sink) => {
+                if let Err(e) = sink.write_all(&entry) {
+                    warn!("WARNING: Could not write capture. {}", e);
+                }
+            }
+            Err(_) => warn!("WARNING: Capture is poisoned, record is lost."),
+        }
+    }
+}
+
+/// Transport that captures every packet sent through it
+pub struct CapturingTransport {
+    neighbor: NodeId,
+    inner: Box<dyn PacketTransport>,
+    capture: Capture,
+}
+
+impl CapturingTransport {
+    pub fn new(neighbor: NodeId, inner: Box<dyn PacketTransport>, capture: Capture) -> Self {
+        CapturingTransport {
+            neighbor,
+            inner,
+            capture,
+        }
+    }
+}
+
+impl PacketTransport for CapturingTransport {
+    fn send(&self, packet: Packet) -> Result<(), SendError<Packet>> {
+        self.capture.sent(self.neighbor, &packet);
+        self.inner.send(packet)
+    }
+}
+
+/// Reads the records of a capture file
+pub struct CaptureReader<R: Read> {
+    reader: R,
+}
+
+impl CaptureReader<BufReader<File>> {
+    pub fn open(path: &Path) -> Result<Self, CodecError> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, CodecError> {
+        let mut header = [0; 5];
+        let available = codec::read_full(&mut reader, &mut header)?;
+        if available < header.len() || &header[..4] != MAGIC {
+            return Err(CodecError::Invalid(String::from("Not a capture file")));
+        }
+        if header[4] != CAPTURE_VERSION {
+            return Err(CodecError::UnsupportedVersion(header[4]));
+        }
+        Ok(CaptureReader { reader })
+    }
+
+    /// Next record, None if the capture ended between two records
+    pub fn read(&mut self) -> Result<Option<CaptureEntry>, CodecError> {
+        let mut micros = [0; 8];
+        let available = codec::read_full(&mut self.reader, &mut micros)?;
+        if available == 0 {
+            return Ok(None);
+        }
+        if available < micros.len() {
+            return Err(CodecError::Truncated {
+                expected: micros.len(),
+                available,
+            });
+        }
+        let at = Duration::from_micros(u64::from_be_bytes(micros));
+
+        let [tag] = self.read_bytes::<1>()?;
+        let record = match tag {
+            TAG_START => {
+                let [server] = self.read_bytes::<1>()?;
+                let count = u16::from_be_bytes(self.read_bytes::<2>()?);
+                let mut neighbors = vec![0; count as usize];
+                let available = codec::read_full(&mut self.reader, &mut neighbors)?;
+                if available < neighbors.len() {
+                    return Err(CodecError::Truncated {
+                        expected: neighbors.len(),
+                        available,
+                    });
+                }
+                CaptureRecord::Start {
+                    server,
+                    neighbors,
+                    session_id: u64::from_be_bytes(self.read_bytes::<8>()?),
+                    flood_id: u64::from_be_bytes(self.read_bytes::<8>()?),
+                }
+            }
+            TAG_DISCOVER => CaptureRecord::Discover,
+            TAG_RECEIVED => CaptureRecord::Received(self.read_packet()?),
+            TAG_SENT => {
+                let [neighbor] = self.read_bytes::<1>()?;
+                CaptureRecord::Sent {
+                    neighbor,
+                    packet: self.read_packet()?,
+                }
+            }
+            TAG_ADD_SENDER => CaptureRecord::AddSender(self.read_bytes::<1>()?[0]),
+            TAG_REMOVE_SENDER => CaptureRecord::RemoveSender(self.read_bytes::<1>()?[0]),
+            TAG_KILL => CaptureRecord::Kill,
+            tag => {
+                return Err(CodecError::Invalid(format!(
+                    "Unknown capture record {}",
+                    tag
+                )))
+            }
+        };
+        Ok(Some(CaptureEntry { at, record }))
+    }
+
+    /// All remaining records
+    pub fn read_all(&mut self) -> Result<Vec<CaptureEntry>, CodecError> {
+        let mut entries = Vec::new();
+        while let Some(entry) = self.read()? {
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    fn read_bytes<const N: usize>(&mut self) -> Result<[u8; N], CodecError> {
+        let mut bytes = [0; N];
+        let available = codec::read_full(&mut self.reader, &mut bytes)?;
+        if available < N {
+            return Err(CodecError::Truncated {
+                expected: N,
+                available,
+            });
+        }
+        Ok(bytes)
+    }
+
+    fn read_packet(&mut self) -> Result<Packet, CodecError> {
+        codec::read_frame(&mut self.reader)?.ok_or_else(|| {
+            CodecError::Invalid(String::from("Capture ends in the middle of a record"))
+        })
+    }
+}
+
+/// Packets sent by the captured server and by the server replaying the capture, per neighbor
+#[derive(Default)]
+pub struct ReplayReport {
+    pub expected: HashMap<NodeId, Vec<Packet>>,
+    pub actual: HashMap<NodeId, Vec<Packet>>,
+}
+
+impl ReplayReport {
+    /// Neighbors to which the replaying server sent other packets than the captured server
+    pub fn mismatches(&self) -> Vec<NodeId> {
+        let mut neighbors: Vec<NodeId> = self
+            .expected
+            .keys()
+            .chain(self.actual.keys())
+            .cloned()
+            .collect();
+        neighbors.sort();
+        neighbors.dedup();
+
+        neighbors
+            .into_iter()
+            .filter(|neighbor| {
+                // Packets are compared by their encoding
+                let encode = |packets: Option<&Vec<Packet>>| -> Vec<Option<Vec<u8>>> {
+                    packets
+                        .map(|packets| packets.iter().map(|p| codec::encode(p).ok()).collect())
+                        .unwrap_or_default()
+                };
+                encode(self.expected.get(neighbor)) != encode(self.actual.get(neighbor))
+            })
+            .collect()
+    }
+
+    pub fn matches(&self) -> bool {
+        self.mismatches().is_empty()
+    }
+}
+
+/// Feed a capture into a fresh server, at the times it was captured, and collect what it sends.
+/// The server runs on a virtual clock (replacing the clock of the config), so the replay does not wait.
+pub fn replay<T: ServerProtocol>(
+    entries: &[CaptureEntry],
+    protocol: T,
+    config: ServerConfig,
+) -> Result<ReplayReport, CodecError> {
+    let Some(CaptureEntry {
+        record:
+            CaptureRecord::Start {
+                server: id,
+                neighbors,
+                session_id,
+                flood_id,
+            },
+        ..
+    }) = entries.first()
+    else {
+        return Err(CodecError::Invalid(String::from(
+            "Capture does not start with the state of the server",
+        )));
+    };
+
+    // Events are not part of the comparison, but the channel has to stay open
+    let (controller_send, _controller_events) = unbounded::<LeafEvent>();
+    let (command_send, controller_recv) = unbounded::<LeafCommand>();
+    let (packet_send, packet_recv) = unbounded::<Packet>();
+    let mut neighbor_recv = HashMap::<NodeId, Receiver<Packet>>::new();
+    let mut transports = HashMap::new();
+    for neighbor in neighbors {
+        let (send, recv) = unbounded::<Packet>();
+        transports.insert(*neighbor, send);
+        neighbor_recv.insert(*neighbor, recv);
+    }
+
+    let clock = VirtualClock::new();
+    let mut server = Server::create(
+        *id,
+        controller_send,
+        controller_recv,
+        packet_recv,
+        transports,
+        protocol,
+        config.with_clock(Clock::Virtual(clock.clone())),
+    );
+    server.senders.session_id = *session_id;
+    server.senders.flood_id = *flood_id;
+
+    let mut report = ReplayReport::default();
+    for entry in &entries[1..] {
+        // Let time pass as it did in the capture, stopping at every timer that is due before the entry
+        while server.running {
+            let next_timer = clock.elapsed() + server.next_timeout().max(MIN_REPLAY_STEP);
+            if next_timer >= entry.at {
+                break;
+            }
+            clock.advance(next_timer - clock.elapsed());
+            settle(&mut server);
+            collect(&neighbor_recv, &mut report.actual);
+        }
+        clock.advance(entry.at.saturating_sub(clock.elapsed()));
+
+        let input = match &entry.record {
+            CaptureRecord::Start { .. } => {
+                return Err(CodecError::Invalid(String::from(
+                    "Capture contains the start of another server",
+                )))
+            }
+            CaptureRecord::Discover => {
+                server.discover();
+                false
+            }
+            CaptureRecord::Received(packet) => packet_send.send(packet.clone()).is_ok(),
+            CaptureRecord::Sent { neighbor, packet } => {
+                report
+                    .expected
+                    .entry(*neighbor)
+                    .or_default()
+                    .push(packet.clone());
+                false
+            }
+            CaptureRecord::AddSender(node_id) => {
+                collect(&neighbor_recv, &mut report.actual);
+                let (send, recv) = unbounded::<Packet>();
+                neighbor_recv.insert(*node_id, recv);
+                command_send
+                    .send(LeafCommand::AddSender(*node_id, send))
+                    .is_ok()
+            }
+            CaptureRecord::RemoveSender(node_id) => command_send
+                .send(LeafCommand::RemoveSender(*node_id))
+                .is_ok(),
+            CaptureRecord::Kill => command_send.send(LeafCommand::Kill).is_ok(),
+        };
+        if input && server.running {
+            settle(&mut server);
+        }
+        collect(&neighbor_recv, &mut report.actual);
+    }
+    Ok(report)
+}
+
+/// Process everything that arrived, including the replies to requests handled on other threads
+fn settle<T: ServerProtocol>(server: &mut Server<T>) {
+    server.poll();
+    while server.running && server.protocol.pending_requests() > 0 {
+        thread::sleep(Duration::from_millis(1));
+    }
+    server.poll();
+}
+
+/// Move everything sent to the neighbors into the report
+fn collect(
+    neighbor_recv: &HashMap<NodeId, Receiver<Packet>>,
+    actual: &mut HashMap<NodeId, Vec<Packet>>,
+) {
+    for (neighbor, recv) in neighbor_recv {
+        for packet in recv.try_iter() {
+            actual.entry(*neighbor).or_default().push(packet);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Source of the current time of a server
+#[derive(Debug, Clone, Default)]
+pub enum Clock {
+    /// Time passes as it does in the real world
+    #[default]
+    System,
+    /// Time only passes when told to, e.g. in a simulation
+    Virtual(VirtualClock),
+}
+
+impl Clock {
+    pub fn now(&self) -> Instant {
+        match self {
+            Clock::System => Instant::now(),
+            Clock::Virtual(clock) => clock.now(),
+        }
+    }
+
+    /// Time since the unix epoch, to make ids unique across restarts
+    /// A virtual clock starts at the epoch, so ids do not depend on when a simulation runs
+    pub fn since_epoch(&self) -> Duration {
+        match self {
+            Clock::System => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or(Duration::ZERO),
+            Clock::Virtual(clock) => clock.elapsed(),
+        }
+    }
+}
+
+/// Clock that is advanced by hand, clones share the same time
+#[derive(Debug, Clone)]
+pub struct VirtualClock {
+    start: Instant,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        VirtualClock {
+            start: Instant::now(),
+            elapsed: Arc::new(Mutex::new(Duration::ZERO)),
+        }
+    }
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    /// Time passed since the clock was created
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+            .lock()
+            .map(|elapsed| *elapsed)
+            .unwrap_or(Duration::ZERO)
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        if let Ok(mut elapsed) = self.elapsed.lock() {
+            *elapsed += duration;
+        }
+    }
+}
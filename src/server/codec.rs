@@ -0,0 +1,196 @@
+use std::{
+    error::Error,
+    fmt::{Display, Formatter},
+    io::{self, Read, Write},
+};
+
+use wg_2024::packet::Packet;
+
+/// Version of the wire format, stored in every frame
+pub const WIRE_VERSION: u8 = 1;
+/// Maximum size of the encoded packet in a frame
+pub const MAX_FRAME_LENGTH: usize = 64 * 1024;
+/// Version (1 byte) + length of the encoded packet (4 bytes, big endian)
+const HEADER_LENGTH: usize = 5;
+
+/// Everything that can go wrong when encoding or decoding a frame
+#[derive(Debug)]
+pub enum CodecError {
+    /// The frame was written with a wire format we do not know
+    UnsupportedVersion(u8),
+    /// The frame ended before the announced amount of bytes
+    Truncated {
+        expected: usize,
+        available: usize,
+    },
+    /// The encoded packet is larger than the maximum frame length
+    Oversized(usize),
+    /// The bytes in the frame are not a packet
+    Invalid(String),
+    Io(io::Error),
+}
+
+impl Display for CodecError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CodecError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported wire format version {}", version)
+            }
+            CodecError::Truncated {
+                expected,
+                available,
+            } => write!(
+                f,
+                "Frame is truncated, expected {} bytes but only {} are available",
+                expected, available
+            ),
+            CodecError::Oversized(length) => write!(
+                f,
+                "Frame of {} bytes exceeds the maximum of {} bytes",
+                length, MAX_FRAME_LENGTH
+            ),
+            CodecError::Invalid(e) => write!(f, "Frame does not contain a packet: {}", e),
+            CodecError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for CodecError {}
+
+impl From<io::Error> for CodecError {
+    fn from(e: io::Error) -> Self {
+        CodecError::Io(e)
+    }
+}
+
+/// Encode a packet as a single frame
+pub fn encode(packet: &Packet) -> Result<Vec<u8>, CodecError> {
+    let body = bincode::serialize(packet).map_err(|e| CodecError::Invalid(e.to_string()))?;
+    if body.len() > MAX_FRAME_LENGTH {
+        return Err(CodecError::Oversized(body.len()));
+    }
+
+    let mut frame = Vec::with_capacity(HEADER_LENGTH + body.len());
+    frame.push(WIRE_VERSION);
+    frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&body);
+    Ok(frame)
+}
+
+/// Decode the frame at the start of the bytes, returns the packet and the length of the frame
+pub fn decode(bytes: &[u8]) -> Result<(Packet, usize), CodecError> {
+    if bytes.len() < HEADER_LENGTH {
+        return Err(CodecError::Truncated {
+            expected: HEADER_LENGTH,
+            available: bytes.len(),
+        });
+    }
+    let length = read_header(&bytes[..HEADER_LENGTH])?;
+
+    let frame_length = HEADER_LENGTH + length;
+    if bytes.len() < frame_length {
+        return Err(CodecError::Truncated {
+            expected: frame_length,
+            available: bytes.len(),
+        });
+    }
+    let packet = decode_body(&bytes[HEADER_LENGTH..frame_length])?;
+    Ok((packet, frame_length))
+}
+
+/// Check the header of a frame, returns the length of the encoded packet
+fn read_header(header: &[u8]) -> Result<usize, CodecError> {
+    if header[0] != WIRE_VERSION {
+        return Err(CodecError::UnsupportedVersion(header[0]));
+    }
+    let length = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if length > MAX_FRAME_LENGTH {
+        return Err(CodecError::Oversized(length));
+    }
+    Ok(length)
+}
+
+fn decode_body(body: &[u8]) -> Result<Packet, CodecError> {
+    bincode::deserialize(body).map_err(|e| CodecError::Invalid(e.to_string()))
+}
+
+/// Writes packets as frames to a byte stream
+pub struct PacketEncoder<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PacketEncoder<W> {
+    pub fn new(writer: W) -> Self {
+        PacketEncoder { writer }
+    }
+
+    pub fn write(&mut self, packet: &Packet) -> Result<(), CodecError> {
+        self.writer.write_all(&encode(packet)?)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), CodecError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Reads packets from a byte stream of frames
+pub struct PacketDecoder<R: Read> {
+    reader: R,
+}
+
+impl<R: Read> PacketDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        PacketDecoder { reader }
+    }
+
+    /// Next packet in the stream, None if the stream ended between two frames
+    pub fn read(&mut self) -> Result<Option<Packet>, CodecError> {
+        read_frame(&mut self.reader)
+    }
+}
+
+/// Read the next frame from a byte stream, None if the stream ended before the frame started
+pub fn read_frame<R: Read>(reader: &mut R) -> Result<Option<Packet>, CodecError> {
+    let mut header = [0; HEADER_LENGTH];
+    let available = read_full(reader, &mut header)?;
+    if available == 0 {
+        return Ok(None);
+    }
+    if available < HEADER_LENGTH {
+        return Err(CodecError::Truncated {
+            expected: HEADER_LENGTH,
+            available,
+        });
+    }
+    let length = read_header(&header)?;
+
+    let mut body = vec![0; length];
+    let available = read_full(reader, &mut body)?;
+    if available < length {
+        return Err(CodecError::Truncated {
+            expected: HEADER_LENGTH + length,
+            available: HEADER_LENGTH + available,
+        });
+    }
+    decode_body(&body).map(Some)
+}
+
+/// Fill the buffer as far as the stream allows, returns the amount of bytes read
+pub fn read_full<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<usize, CodecError> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(CodecError::Io(e)),
+        }
+    }
+    Ok(filled)
+}
@@ -0,0 +1,168 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+use wg_2024::packet::PacketType;
+
+use super::{
+    Clock, ConfigError, HistoryLimits, OutboundLimits, ReassemblyLimits, RetransmitPolicy,
+};
+
+/// Which packets may be sent through the Simulation Controller when the neighbor cannot be reached
+#[derive(Debug, Clone)]
+pub struct ShortcutPolicy {
+    pub acks: bool,
+    pub nacks: bool,
+    pub flood_responses: bool,
+}
+
+impl ShortcutPolicy {
+    /// Only acks, nacks and flood responses can ever use the shortcut
+    pub fn allows(&self, pack_type: &PacketType) -> bool {
+        match pack_type {
+            PacketType::Ack(_) => self.acks,
+            PacketType::Nack(_) => self.nacks,
+            PacketType::FloodResponse(_) => self.flood_responses,
+            _ => false,
+        }
+    }
+}
+
+impl Default for ShortcutPolicy {
+    fn default() -> Self {
+        ShortcutPolicy {
+            acks: true,
+            nacks: true,
+            flood_responses: true,
+        }
+    }
+}
+
+/// How to choose between the routes to a node
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoutingStrategy {
+    /// Route with the lowest estimated chance of being dropped (fewest hops if equally reliable)
+    #[default]
+    MostReliable,
+    /// Route with the fewest hops, ignoring drop rates
+    FewestHops,
+}
+
+/// Everything about the behavior of a server that can be tuned
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// When and how often to resend fragments that have not been acknowledged
+    pub retransmit: RetransmitPolicy,
+    /// Size of the history of unacknowledged packets
+    pub history: HistoryLimits,
+    /// Limits on the messages being reassembled
+    pub reassembly: ReassemblyLimits,
+    /// Limits on the messages waiting for a route
+    pub outbound: OutboundLimits,
+    pub shortcut: ShortcutPolicy,
+    pub routing: RoutingStrategy,
+    /// Time between two ticks of the server
+    pub tick_interval: Duration,
+    /// Time to wait for outstanding fragments to be acknowledged after being killed
+    pub drain_timeout: Duration,
+    /// Minimum time between two floods started by the server
+    pub min_flood_interval: Duration,
+    /// Where the server gets the current time from
+    pub clock: Clock,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            retransmit: RetransmitPolicy::default(),
+            history: HistoryLimits::default(),
+            reassembly: ReassemblyLimits::default(),
+            outbound: OutboundLimits::default(),
+            shortcut: ShortcutPolicy::default(),
+            routing: RoutingStrategy::default(),
+            tick_interval: Duration::from_millis(100),
+            drain_timeout: Duration::from_secs(5),
+            min_flood_interval: Duration::from_millis(500),
+            clock: Clock::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Check for values the server cannot run with
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        match self.clone().sanitize().into_iter().next() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Replace values the server cannot run with by their default, returns what was wrong with each of them
+    pub fn sanitize(&mut self) -> Vec<ConfigError> {
+        let default = ServerConfig::default();
+        let mut errors = Vec::new();
+        if self.tick_interval.is_zero() {
+            self.tick_interval = default.tick_interval;
+            errors.push(ConfigError::Zero("tick_interval"));
+        }
+        if self.retransmit.ack_timeout.is_zero() {
+            self.retransmit.ack_timeout = default.retransmit.ack_timeout;
+            errors.push(ConfigError::Zero("retransmit.ack_timeout"));
+        }
+        if self.retransmit.backoff == 0 {
+            self.retransmit.backoff = default.retransmit.backoff;
+            errors.push(ConfigError::Zero("retransmit.backoff"));
+        }
+        errors
+    }
+
+    pub fn with_retransmit_policy(mut self, policy: RetransmitPolicy) -> Self {
+        self.retransmit = policy;
+        self
+    }
+
+    pub fn with_history_limits(mut self, limits: HistoryLimits) -> Self {
+        self.history = limits;
+        self
+    }
+
+    pub fn with_reassembly_limits(mut self, limits: ReassemblyLimits) -> Self {
+        self.reassembly = limits;
+        self
+    }
+
+    pub fn with_outbound_limits(mut self, limits: OutboundLimits) -> Self {
+        self.outbound = limits;
+        self
+    }
+
+    pub fn with_shortcut_policy(mut self, policy: ShortcutPolicy) -> Self {
+        self.shortcut = policy;
+        self
+    }
+
+    pub fn with_routing_strategy(mut self, strategy: RoutingStrategy) -> Self {
+        self.routing = strategy;
+        self
+    }
+
+    pub fn with_tick_interval(mut self, interval: Duration) -> Self {
+        self.tick_interval = interval;
+        self
+    }
+
+    pub fn with_drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    pub fn with_min_flood_interval(mut self, interval: Duration) -> Self {
+        self.min_flood_interval = interval;
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+}
@@ -0,0 +1,60 @@
+use std::{
+    error::Error,
+    fmt::{Display, Formatter},
+};
+
+use wg_2024::network::NodeId;
+
+/// Everything that can go wrong when sending to a node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerError {
+    /// The neighbor the route starts with is not connected to us
+    UnknownNeighbor(NodeId),
+    /// No route to the node is known (yet)
+    NoRoute(NodeId),
+    /// The channel to the neighbor is closed, and no shortcut could be taken
+    Disconnected(NodeId),
+    /// The message could not be split into fragments
+    Fragmentation,
+    /// No route to the node is known, and no more messages can be queued for it
+    QueueFull(NodeId),
+}
+
+impl Display for ServerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServerError::UnknownNeighbor(node_id) => write!(f, "Unknown neighbor {}", node_id),
+            ServerError::NoRoute(node_id) => write!(f, "No route known to {}", node_id),
+            ServerError::Disconnected(node_id) => {
+                write!(f, "Channel to neighbor {} is disconnected", node_id)
+            }
+            ServerError::Fragmentation => write!(f, "Message could not be split into fragments"),
+            ServerError::QueueFull(node_id) => {
+                write!(
+                    f,
+                    "Queue of messages waiting for a route to {} is full",
+                    node_id
+                )
+            }
+        }
+    }
+}
+
+impl Error for ServerError {}
+
+/// Value in the server configuration the server cannot run with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The value must be larger than 0, e.g. a zero interval would make the server spin
+    Zero(&'static str),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Zero(name) => write!(f, "{} must be larger than 0", name),
+        }
+    }
+}
+
+impl Error for ConfigError {}
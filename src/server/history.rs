@@ -0,0 +1,290 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    time::{Duration, Instant},
+};
+
+use common_structs::types::{FragmentIdx, Session};
+use log::{info, warn};
+use wg_2024::{
+    network::NodeId,
+    packet::{Packet, PacketType},
+};
+
+use super::RetransmitPolicy;
+
+/// Destination + session id + fragment index of a packet that was sent
+/// Sessions are chosen by whoever starts them, so they are only unique per destination
+pub type HistoryKey = (NodeId, Session, FragmentIdx);
+
+/// A packet we sent, together with the information required to resend it
+pub struct SentPacket {
+    pub packet: Packet,
+    /// Last time the packet was (re)sent
+    pub sent_at: Instant,
+    /// Number of times the packet was resent because no ack arrived in time
+    pub retries: u32,
+    /// Position in the least recently used order
+    last_use: u64,
+    /// Time to resend the packet if no ack arrived, None if it never times out
+    deadline: Option<Instant>,
+}
+
+/// Limits on the packets kept in the history, the least recently used packets are evicted first
+#[derive(Debug, Clone)]
+pub struct HistoryLimits {
+    pub max_entries: usize,
+    /// Maximum amount of fragment data bytes
+    pub max_bytes: usize,
+}
+
+impl Default for HistoryLimits {
+    fn default() -> Self {
+        HistoryLimits {
+            max_entries: 8192,
+            max_bytes: 1024 * 1024,
+        }
+    }
+}
+
+/// Counters of packets removed from the history, per reason
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HistoryEvictions {
+    /// Packets that were acknowledged
+    pub acked: u64,
+    /// Sessions of which every packet was acknowledged
+    pub completed_sessions: u64,
+    /// Packets of which no ack arrived after the maximum amount of retries
+    pub given_up: u64,
+    /// Packets removed to stay within the history limits
+    pub over_capacity: u64,
+}
+
+/// History of the packets we sent which have not been acknowledged yet
+pub struct PacketHistory {
+    entries: HashMap<HistoryKey, SentPacket>,
+    /// Per last use, the packet that was used
+    lru: BTreeMap<u64, HistoryKey>,
+    /// Packets ordered by the time they should be resent, so timeouts do not scan the whole history
+    deadlines: BTreeSet<(Instant, HistoryKey)>,
+    next_use: u64,
+    /// Per destination + session, the amount of packets that are not acknowledged yet
+    sessions: HashMap<(NodeId, Session), usize>,
+    bytes: usize,
+    limits: HistoryLimits,
+    retransmit: RetransmitPolicy,
+    evictions: HistoryEvictions,
+}
+
+impl PacketHistory {
+    pub fn new(limits: HistoryLimits, retransmit: RetransmitPolicy) -> Self {
+        PacketHistory {
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            deadlines: BTreeSet::new(),
+            next_use: 0,
+            sessions: HashMap::new(),
+            bytes: 0,
+            limits,
+            retransmit,
+            evictions: HistoryEvictions::default(),
+        }
+    }
+
+    pub fn evictions(&self) -> &HistoryEvictions {
+        &self.evictions
+    }
+
+    pub fn get(&self, key: &HistoryKey) -> Option<&SentPacket> {
+        self.entries.get(key)
+    }
+
+    /// Record a packet that was (re)sent, a resend keeps the retry count of the packet
+    pub fn insert(&mut self, packet: Packet, now: Instant) {
+        let Some(destination) = packet.routing_header.destination() else {
+            warn!("WARNING: Not recording packet without destination.");
+            return;
+        };
+        let key = (destination, packet.session_id, packet.get_fragment_index());
+        let retries = match self.remove(&key) {
+            Some(previous) => previous.retries,
+            None => 0,
+        };
+
+        self.bytes += Self::data_len(&packet);
+        *self.sessions.entry((key.0, key.1)).or_insert(0) += 1;
+        self.lru.insert(self.next_use, key);
+        let deadline = now.checked_add(self.retransmit.timeout(retries));
+        if let Some(deadline) = deadline {
+            self.deadlines.insert((deadline, key));
+        }
+        self.entries.insert(
+            key,
+            SentPacket {
+                packet,
+                sent_at: now,
+                retries,
+                last_use: self.next_use,
+                deadline,
+            },
+        );
+        self.next_use += 1;
+
+        self.enforce_limits();
+    }
+
+    /// Amount of packets that are not acknowledged yet
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Whether packets of a session to a node are not acknowledged yet
+    pub fn has_session(&self, to: NodeId, session_id: Session) -> bool {
+        self.sessions.contains_key(&(to, session_id))
+    }
+
+    /// Packet a nack refers to, the nack travelled back along the first part of the route of the packet
+    pub fn find_nacked(
+        &self,
+        session_id: Session,
+        fragment_index: FragmentIdx,
+        nack_hops: &[NodeId],
+    ) -> Option<HistoryKey> {
+        let mut candidates = self
+            .entries
+            .iter()
+            .filter(|((_, session, index), _)| *session == session_id && *index == fragment_index);
+        let first = candidates.next()?;
+        let Some(second) = candidates.next() else {
+            // Only one packet can be meant
+            return Some(*first.0);
+        };
+
+        [first, second]
+            .into_iter()
+            .chain(candidates)
+            .find(|(_, sent)| {
+                let hops = &sent.packet.routing_header.hops;
+                hops.len() >= nack_hops.len()
+                    && hops.iter().zip(nack_hops.iter().rev()).all(|(a, b)| a == b)
+            })
+            .map(|(key, _)| *key)
+    }
+
+    /// Increase the retry count of a packet, which is then waited for longer
+    pub fn record_retry(&mut self, key: &HistoryKey) {
+        if let Some(sent) = self.entries.get_mut(key) {
+            sent.retries += 1;
+            // A parked packet keeps waiting for a route
+            if let Some(deadline) = sent.deadline.take() {
+                self.deadlines.remove(&(deadline, *key));
+                sent.deadline = sent
+                    .sent_at
+                    .checked_add(self.retransmit.timeout(sent.retries));
+                if let Some(deadline) = sent.deadline {
+                    self.deadlines.insert((deadline, *key));
+                }
+            }
+        }
+    }
+
+    /// Stop timing out a packet that waits for a route, resending it starts the timeout again
+    pub fn park(&mut self, key: &HistoryKey) {
+        if let Some(deadline) = self
+            .entries
+            .get_mut(key)
+            .and_then(|sent| sent.deadline.take())
+        {
+            self.deadlines.remove(&(deadline, *key));
+        }
+    }
+
+    /// Remove a packet that was acknowledged, returns if the packet was known
+    pub fn ack(&mut self, key: &HistoryKey) -> bool {
+        let known = self.remove(key).is_some();
+        if known {
+            self.evictions.acked += 1;
+
+            if !self.sessions.contains_key(&(key.0, key.1)) {
+                self.evictions.completed_sessions += 1;
+                info!(
+                    "All packets of session {} to {} are acknowledged.",
+                    key.1, key.0
+                );
+            }
+        }
+        known
+    }
+
+    /// Remove a packet that will not be resent anymore
+    pub fn give_up(&mut self, key: &HistoryKey) {
+        if self.remove(key).is_some() {
+            self.evictions.given_up += 1;
+        }
+    }
+
+    /// All packets of which the ack did not arrive in time, the ones that timed out first first
+    pub fn timed_out(&self, now: Instant) -> Vec<HistoryKey> {
+        self.deadlines
+            .iter()
+            .take_while(|(deadline, _)| *deadline <= now)
+            .map(|(_, key)| *key)
+            .collect()
+    }
+
+    /// All packets of which the route starts with the given neighbor
+    pub fn routed_through(&self, neighbor_id: NodeId) -> Vec<HistoryKey> {
+        self.entries
+            .iter()
+            .filter(|(_, sent)| sent.packet.routing_header.current_hop() == Some(neighbor_id))
+            .map(|(key, _)| *key)
+            .collect()
+    }
+
+    /// Time until the first packet times out
+    pub fn next_timeout(&self, now: Instant) -> Option<Duration> {
+        self.deadlines
+            .first()
+            .map(|(deadline, _)| deadline.saturating_duration_since(now))
+    }
+
+    fn remove(&mut self, key: &HistoryKey) -> Option<SentPacket> {
+        let sent = self.entries.remove(key)?;
+        self.lru.remove(&sent.last_use);
+        if let Some(deadline) = sent.deadline {
+            self.deadlines.remove(&(deadline, *key));
+        }
+        self.bytes -= Self::data_len(&sent.packet);
+
+        if let Some(pending) = self.sessions.get_mut(&(key.0, key.1)) {
+            *pending -= 1;
+            if *pending == 0 {
+                self.sessions.remove(&(key.0, key.1));
+            }
+        }
+
+        Some(sent)
+    }
+
+    /// Evict the least recently used packets until the history is within its limits
+    fn enforce_limits(&mut self) {
+        while self.entries.len() > self.limits.max_entries || self.bytes > self.limits.max_bytes {
+            let Some((_, key)) = self.lru.pop_first() else {
+                break;
+            };
+            if self.remove(&key).is_some() {
+                self.evictions.over_capacity += 1;
+            }
+        }
+    }
+
+    fn data_len(packet: &Packet) -> usize {
+        match &packet.pack_type {
+            PacketType::MsgFragment(fragment) => fragment.length as usize,
+            _ => 0,
+        }
+    }
+}
@@ -0,0 +1,1482 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use common_structs::{
+    leaf::{LeafCommand, LeafEvent},
+    message::Message,
+    types::{Routing, Session},
+};
+use crossbeam_channel::{never, select_biased, tick, Receiver, SendError, Sender};
+use log::{info, warn};
+use wg_2024::{
+    network::NodeId,
+    packet::{
+        Ack, FloodRequest, FloodResponse, Fragment, Nack, NackType, NodeType, Packet, PacketType,
+    },
+};
+
+mod capture;
+mod clock;
+pub mod codec;
+mod config;
+mod error;
+mod history;
+mod outbound;
+mod pool;
+mod reassembly;
+mod stats;
+mod topology;
+mod transport;
+
+pub use capture::{
+    replay, Capture, CaptureEntry, CaptureReader, CaptureRecord, CapturingTransport, ReplayReport,
+};
+pub use clock::{Clock, VirtualClock};
+pub use config::{RoutingStrategy, ServerConfig, ShortcutPolicy};
+pub use error::{ConfigError, ServerError};
+pub use history::{HistoryEvictions, HistoryKey, HistoryLimits, PacketHistory};
+pub use outbound::{OutboundLimits, OutboundQueue, QueuedMessage};
+pub use pool::{ConcurrentProtocol, Reply, WorkerPool};
+pub use reassembly::{FragmentOutcome, Reassembly, ReassemblyBuffer, ReassemblyLimits};
+pub use stats::{NackCounts, ServerReport, ServerStats};
+pub use topology::Topology;
+pub use transport::{spawn_udp_receiver, PacketTransport, UdpTransport};
+
+/// Information required to send a packet.
+pub struct PreparedNodeSend<'a> {
+    routing: &'a Routing,
+    session: Session,
+    neighbor_id: NodeId,
+    neighbor: &'a dyn PacketTransport,
+    controller: &'a Sender<LeafEvent>,
+    history: &'a mut PacketHistory,
+    stats: &'a mut ServerStats,
+    shortcut: &'a ShortcutPolicy,
+    now: Instant,
+}
+
+/// Per node, the transport to send packets to this node
+pub type PacketSendLookup = HashMap<NodeId, Box<dyn PacketTransport>>;
+/// Per node, the routing to use to send packets to it
+pub type NodePathLookup = HashMap<NodeId, Routing>;
+/// Per node, the packets waiting for a route to this node to be resent
+pub type PendingResendLookup = HashMap<NodeId, Vec<HistoryKey>>;
+
+/// State of the server when it stopped after being killed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DrainSummary {
+    /// Fragments that were never acknowledged
+    pub unacked_fragments: usize,
+    /// Messages that were still waiting for a route
+    pub queued_messages: usize,
+    /// Messages of which not all fragments were received
+    pub incomplete_messages: usize,
+    /// Requests that were still being handled by the protocol
+    pub pending_requests: usize,
+    /// The deadline expired before all fragments were acknowledged
+    pub timed_out: bool,
+}
+
+/// When and how often to resend fragments that have not been acknowledged
+#[derive(Debug, Clone)]
+pub struct RetransmitPolicy {
+    /// Time to wait for an ack before the first resend
+    pub ack_timeout: Duration,
+    /// Factor the timeout is multiplied with after every resend
+    pub backoff: u32,
+    /// Number of resends after which the fragment is given up on
+    pub max_retries: u32,
+}
+
+impl RetransmitPolicy {
+    /// Time to wait for an ack of a packet that has been resent `retries` times
+    pub fn timeout(&self, retries: u32) -> Duration {
+        self.ack_timeout
+            .checked_mul(self.backoff.saturating_pow(retries))
+            .unwrap_or(Duration::MAX)
+    }
+}
+
+impl Default for RetransmitPolicy {
+    fn default() -> Self {
+        RetransmitPolicy {
+            ack_timeout: Duration::from_millis(500),
+            backoff: 2,
+            max_retries: 5,
+        }
+    }
+}
+
+/// Struct to store the information required to send packets
+pub struct ServerSenders {
+    /// Send information to the Simulation Controller
+    controller_send: Sender<LeafEvent>,
+    /// Send information to connected nodes (neighbors)
+    packet_send: PacketSendLookup,
+
+    /// Last session id used for a message we started, incremented for every new message
+    session_id: Session,
+    /// The path to use to reach a certain node (computed from the topology)
+    node_path: NodePathLookup,
+    /// Topology version the paths in node_path were computed for
+    node_path_version: u64,
+    /// The network as far as we know it
+    topology: Topology,
+    /// Id of the last flood we started
+    flood_id: u64,
+    /// Time we last started a flood
+    last_flood: Option<Instant>,
+    /// A flood was needed too soon after the last one, it is started once min_flood_interval has passed
+    flood_wanted: bool,
+    /// History of packets we sent which have not been acknowledged yet
+    history: PacketHistory,
+    /// Messages waiting for a route to their destination
+    outbound: OutboundQueue,
+    /// When to resend packets that are not acknowledged
+    retransmit: RetransmitPolicy,
+    /// Which packets may be sent through the Simulation Controller
+    shortcut: ShortcutPolicy,
+    /// Minimum time between two floods
+    min_flood_interval: Duration,
+    /// Counters of everything the server did
+    stats: ServerStats,
+    /// Source of the current time
+    clock: Clock,
+}
+
+impl ServerSenders {
+    pub fn new<P: PacketTransport + 'static>(
+        id: NodeId,
+        controller_send: Sender<LeafEvent>,
+        packet_send: HashMap<NodeId, P>,
+        config: &ServerConfig,
+    ) -> Self {
+        // Neighbors are directly connected to us
+        let mut topology = Topology::new(id);
+        topology.set_strategy(config.routing);
+        for neighbor_id in packet_send.keys() {
+            topology.add_edge(id, *neighbor_id);
+        }
+
+        ServerSenders {
+            controller_send,
+            packet_send: Self::boxed(packet_send),
+
+            session_id: Self::initial_id(id, &config.clock),
+            node_path: HashMap::new(),
+            node_path_version: topology.version(),
+            topology,
+            flood_id: Self::initial_id(id, &config.clock),
+            last_flood: None,
+            flood_wanted: false,
+            history: PacketHistory::new(config.history.clone(), config.retransmit.clone()),
+            outbound: OutboundQueue::new(config.outbound.clone()),
+            retransmit: config.retransmit.clone(),
+            shortcut: config.shortcut.clone(),
+            min_flood_interval: config.min_flood_interval,
+            stats: ServerStats::default(),
+            clock: config.clock.clone(),
+        }
+    }
+
+    /// Constructor for unit testing
+    #[allow(dead_code)]
+    pub fn with_node_path<P: PacketTransport + 'static>(
+        id: NodeId,
+        controller_send: Sender<LeafEvent>,
+        packet_send: HashMap<NodeId, P>,
+        node_path: NodePathLookup,
+    ) -> Self {
+        let config = ServerConfig::default();
+        ServerSenders {
+            controller_send,
+            packet_send: Self::boxed(packet_send),
+            node_path,
+
+            session_id: Self::initial_id(id, &config.clock),
+            node_path_version: 0,
+            topology: Topology::new(id),
+            flood_id: Self::initial_id(id, &config.clock),
+            last_flood: None,
+            flood_wanted: false,
+            history: PacketHistory::new(config.history, config.retransmit.clone()),
+            outbound: OutboundQueue::new(config.outbound),
+            retransmit: config.retransmit,
+            shortcut: config.shortcut,
+            min_flood_interval: config.min_flood_interval,
+            stats: ServerStats::default(),
+            clock: config.clock,
+        }
+    }
+
+    /// Allow neighbors to be reached through different kinds of transports
+    fn boxed<P: PacketTransport + 'static>(packet_send: HashMap<NodeId, P>) -> PacketSendLookup {
+        packet_send
+            .into_iter()
+            .map(|(neighbor_id, transport)| {
+                (neighbor_id, Box::new(transport) as Box<dyn PacketTransport>)
+            })
+            .collect()
+    }
+
+    /// Flood and session ids have to be unique per initiator, also after a restart of the server
+    fn initial_id(id: NodeId, clock: &Clock) -> u64 {
+        let time = clock.since_epoch().as_nanos() as u64;
+        ((id as u64) << 56) | (time & ((1 << 56) - 1))
+    }
+
+    /// Make sure the path to a node is computed from the current topology
+    fn refresh_route(&mut self, to: NodeId) {
+        if self.node_path_version != self.topology.version() {
+            self.node_path.clear();
+            self.node_path_version = self.topology.version();
+        }
+
+        if !self.node_path.contains_key(&to) {
+            if let Some(route) = self.topology.route_to(to) {
+                info!("Using route to {}: {}", to, route);
+                self.node_path.insert(to, route);
+            }
+        }
+    }
+
+    /// Whether a route to a node is known, starting with a connected neighbor
+    fn has_route(&mut self, to: NodeId) -> bool {
+        self.refresh_route(to);
+        self.node_path
+            .get(&to)
+            .and_then(|route| route.current_hop())
+            .is_some_and(|neighbor_id| self.packet_send.contains_key(&neighbor_id))
+    }
+
+    /// Time until a new flood may be started, None if no flood was started yet
+    fn next_flood(&self, now: Instant) -> Option<Duration> {
+        self.last_flood.map(|last_flood| {
+            self.min_flood_interval
+                .saturating_sub(now.saturating_duration_since(last_flood))
+        })
+    }
+}
+
+/// Struct to store the information required to receive packets
+pub struct ServerReceivers {
+    controller_recv: Receiver<LeafCommand>,
+    packet_recv: Receiver<Packet>,
+    /// Replies of protocols handling requests outside of the network loop
+    reply_recv: Receiver<Reply>,
+    /// Fires periodically to run time-based work
+    tick_recv: Receiver<Instant>,
+}
+
+impl ServerReceivers {
+    pub fn new(
+        controller_recv: Receiver<LeafCommand>,
+        packet_recv: Receiver<Packet>,
+        reply_recv: Option<Receiver<Reply>>,
+        tick_interval: Duration,
+    ) -> Self {
+        ServerReceivers {
+            controller_recv,
+            packet_recv,
+            reply_recv: reply_recv.unwrap_or_else(never),
+            tick_recv: tick(tick_interval),
+        }
+    }
+}
+
+/// Protocol to use to process messages
+pub trait ServerProtocol {
+    fn on_message(
+        &mut self,
+        server: NodeId,
+        senders: &mut ServerSenders,
+        from: NodeId,
+        message: Message,
+        session_id: Session,
+    );
+
+    /// Replies to send, for protocols that do not send from on_message (e.g. when handling requests on other threads)
+    fn replies(&self) -> Option<Receiver<Reply>> {
+        None
+    }
+
+    /// Called periodically, e.g. to expire state that was not used for a while
+    fn on_tick(&mut self, _server: NodeId, _senders: &mut ServerSenders, _now: Instant) {}
+
+    /// Requests that are still being handled (e.g. on other threads), waited for when killed
+    fn pending_requests(&self) -> usize {
+        0
+    }
+}
+
+/// Struct to store the information required to run a server
+pub struct Server<T: ServerProtocol> {
+    running: bool,
+    id: NodeId,
+    senders: ServerSenders,
+    receivers: ServerReceivers,
+    protocol: T,
+    /// Fragments of messages that are not complete yet
+    reassembly: Reassembly,
+    /// Packets of which the route failed, waiting for a new route
+    pending_resends: PendingResendLookup,
+    /// Time to wait for outstanding fragments after being killed
+    drain_timeout: Duration,
+    /// Set once killed, the server stops when everything is acknowledged or this deadline expires
+    drain_deadline: Option<Instant>,
+    drain_summary: Option<DrainSummary>,
+    /// Receives a snapshot of the statistics on every tick, and dropped messages as they happen
+    report_send: Option<Sender<ServerReport>>,
+    /// Records all traffic of the server
+    capture: Option<Capture>,
+    /// Time between two ticks when polled
+    tick_interval: Duration,
+    last_tick: Option<Instant>,
+}
+
+impl<T: ServerProtocol> Server<T> {
+    pub fn create<P: PacketTransport + 'static>(
+        id: NodeId,
+        controller_send: Sender<LeafEvent>,
+        controller_recv: Receiver<LeafCommand>,
+        packet_recv: Receiver<Packet>,
+        packet_send: HashMap<NodeId, P>,
+        implementation: T,
+        mut config: ServerConfig,
+    ) -> Self {
+        for e in config.sanitize() {
+            warn!(
+                "WARNING: Invalid server config, using the default value. {}",
+                e
+            );
+        }
+        Server {
+            running: true,
+            id,
+            senders: ServerSenders::new(id, controller_send, packet_send, &config),
+            receivers: ServerReceivers::new(
+                controller_recv,
+                packet_recv,
+                implementation.replies(),
+                config.tick_interval,
+            ),
+            protocol: implementation,
+            reassembly: Reassembly::new(config.reassembly),
+            pending_resends: HashMap::new(),
+            drain_timeout: config.drain_timeout,
+            drain_deadline: None,
+            drain_summary: None,
+            report_send: None,
+            capture: None,
+            tick_interval: config.tick_interval,
+            last_tick: None,
+        }
+    }
+
+    /// How the server stopped, once it did
+    pub fn drain_summary(&self) -> Option<&DrainSummary> {
+        self.drain_summary.as_ref()
+    }
+
+    /// Report a snapshot of the statistics on every tick, and dropped messages as they happen
+    /// (e.g. to the simulation controller)
+    pub fn with_report_sender(mut self, report_send: Sender<ServerReport>) -> Self {
+        self.report_send = Some(report_send);
+        self
+    }
+
+    /// Record every packet and command, e.g. to replay the traffic when debugging
+    pub fn with_capture(mut self, capture: Capture) -> Self {
+        let capture = capture.with_clock(self.senders.clock.clone());
+        let mut neighbors: Vec<NodeId> = self.senders.packet_send.keys().cloned().collect();
+        neighbors.sort();
+        capture.start(
+            self.id,
+            &neighbors,
+            self.senders.session_id,
+            self.senders.flood_id,
+        );
+
+        self.senders.packet_send = std::mem::take(&mut self.senders.packet_send)
+            .into_iter()
+            .map(|(neighbor_id, transport)| {
+                let transport: Box<dyn PacketTransport> = Box::new(CapturingTransport::new(
+                    neighbor_id,
+                    transport,
+                    capture.clone(),
+                ));
+                (neighbor_id, transport)
+            })
+            .collect();
+        self.capture = Some(capture);
+        self
+    }
+
+    /// Counters of everything the server did since it started
+    pub fn stats(&self) -> &ServerStats {
+        &self.senders.stats
+    }
+
+    /// Counters of packets removed from the history of unacknowledged packets
+    pub fn history_evictions(&self) -> &HistoryEvictions {
+        self.senders.history.evictions()
+    }
+
+    /// Process one packet (or resend timed out fragments when no packet arrives in time)
+    pub fn update(&mut self) {
+        let timeout = self.next_timer(self.senders.clock.now());
+        select_biased! {
+            recv(self.receivers.controller_recv) -> res => {
+                if let Ok(command) = res {
+                    self.on_command(command);
+                } else {
+                    // Controller hung up, a disconnected channel is always ready and would starve the others
+                    self.receivers.controller_recv = never();
+                }
+            },
+            recv(self.receivers.packet_recv) -> res => {
+                if let Ok(packet) = res {
+                    self.on_packet(packet);
+                } else {
+                    self.receivers.packet_recv = never();
+                }
+            },
+            recv(self.receivers.reply_recv) -> res => {
+                if let Ok(reply) = res {
+                    self.on_reply(reply);
+                } else {
+                    self.receivers.reply_recv = never();
+                }
+            },
+            recv(self.receivers.tick_recv) -> res => {
+                if res.is_ok() {
+                    self.on_tick(self.senders.clock.now());
+                }
+            },
+            default(timeout) => {}
+        }
+
+        self.on_timers(self.senders.clock.now());
+    }
+
+    /// Process everything that arrived without waiting, and do the work that is due by now
+    /// Together with a virtual clock, this runs the server deterministically (e.g. in a simulation)
+    pub fn poll(&mut self) {
+        while let Ok(command) = self.receivers.controller_recv.try_recv() {
+            self.on_command(command);
+        }
+        while let Ok(packet) = self.receivers.packet_recv.try_recv() {
+            self.on_packet(packet);
+        }
+        while let Ok(reply) = self.receivers.reply_recv.try_recv() {
+            self.on_reply(reply);
+        }
+
+        let now = self.senders.clock.now();
+        if self.last_tick.map_or(true, |last_tick| {
+            now.saturating_duration_since(last_tick) >= self.tick_interval
+        }) {
+            self.last_tick = Some(now);
+            self.on_tick(now);
+        }
+        self.on_timers(now);
+    }
+
+    /// Time until the next fragment has to be resent or a flood started, if no packet arrives before then
+    pub fn next_timeout(&self) -> Duration {
+        self.next_timer(self.senders.clock.now())
+    }
+
+    fn on_command(&mut self, command: LeafCommand) {
+        if let Some(capture) = &self.capture {
+            capture.command(&command);
+        }
+        match command {
+            LeafCommand::RemoveSender(node_id) => self.on_remove_sender(node_id),
+            LeafCommand::AddSender(node_id, sender) => self.on_add_sender(node_id, sender),
+            LeafCommand::Kill => self.start_drain(self.senders.clock.now()),
+        };
+    }
+
+    fn on_packet(&mut self, packet: Packet) {
+        if let Some(capture) = &self.capture {
+            capture.received(&packet);
+        }
+        match packet.pack_type {
+            PacketType::MsgFragment(fragment) => {
+                self.on_fragment(packet.routing_header, packet.session_id, fragment);
+            }
+            PacketType::FloodRequest(req) => {
+                self.on_flood_request(req);
+            }
+            PacketType::FloodResponse(resp) => {
+                self.on_flood_response(resp);
+            }
+            PacketType::Nack(nack) => {
+                self.on_nack(packet.routing_header, packet.session_id, nack);
+            }
+            PacketType::Ack(ack) => {
+                self.on_ack(packet.routing_header, packet.session_id, ack);
+            }
+        }
+    }
+
+    fn on_reply(&mut self, reply: Reply) {
+        if let Err(e) = Self::send_message(
+            self.id,
+            &mut self.senders,
+            reply.to,
+            reply.message,
+            reply.session_id,
+        ) {
+            warn!("WARNING: Could not send reply to {}. {}", reply.to, e);
+        }
+    }
+
+    /// Work that depends on time passing
+    fn on_timers(&mut self, now: Instant) {
+        self.retransmit_unacked(now);
+        self.expire_reassembly(now);
+        self.expire_outbound(now);
+        self.forget_stale_resends();
+        self.start_wanted_flood(now);
+        self.check_drained(now);
+    }
+
+    /// Neighbor is connected, it might offer better routes
+    fn on_add_sender(&mut self, node_id: NodeId, sender: Sender<Packet>) {
+        let transport: Box<dyn PacketTransport> = match &self.capture {
+            Some(capture) => Box::new(CapturingTransport::new(
+                node_id,
+                Box::new(sender),
+                capture.clone(),
+            )),
+            None => Box::new(sender),
+        };
+        self.senders.packet_send.insert(node_id, transport);
+
+        // Node is directly connected to us
+        self.senders.topology.add_edge(self.id, node_id);
+        self.flush_waiting();
+
+        // Discover what is behind the new neighbor
+        Self::start_flood(&mut self.senders);
+    }
+
+    /// Neighbor is disconnected, everything sent through it is rerouted
+    fn on_remove_sender(&mut self, node_id: NodeId) {
+        if self.senders.packet_send.remove(&node_id).is_none() {
+            return;
+        }
+        self.senders.topology.remove_edge(self.id, node_id);
+
+        // Packets that were not acknowledged yet probably got lost with the link
+        for key in self.senders.history.routed_through(node_id) {
+            self.resend_or_park(key);
+        }
+    }
+
+    /// Stop accepting new messages, but keep going until the sent fragments are acknowledged
+    fn start_drain(&mut self, now: Instant) {
+        if self.drain_deadline.is_none() {
+            info!(
+                "Killed, waiting for {} unacknowledged fragments and {} queued messages.",
+                self.senders.history.len(),
+                self.senders.outbound.len()
+            );
+            self.drain_deadline = Some(now + self.drain_timeout);
+        }
+    }
+
+    /// Stop the server once draining is done
+    fn check_drained(&mut self, now: Instant) {
+        let Some(deadline) = self.drain_deadline else {
+            return;
+        };
+        let timed_out = now >= deadline;
+        // Replies waiting to be sent are still part of a request being handled
+        let pending_requests = self.protocol.pending_requests() + self.receivers.reply_recv.len();
+        let done = self.senders.history.is_empty()
+            && self.senders.outbound.is_empty()
+            && self.reassembly.is_empty()
+            && pending_requests == 0;
+        if !self.running || !(done || timed_out) {
+            return;
+        }
+
+        let summary = DrainSummary {
+            unacked_fragments: self.senders.history.len(),
+            queued_messages: self.senders.outbound.len(),
+            incomplete_messages: self.reassembly.len(),
+            pending_requests,
+            timed_out,
+        };
+        if summary.timed_out {
+            warn!(
+                "WARNING: Stopped with {} unacknowledged fragments, {} queued messages, {} incomplete messages and {} pending requests.",
+                summary.unacked_fragments,
+                summary.queued_messages,
+                summary.incomplete_messages,
+                summary.pending_requests
+            );
+        } else {
+            info!("Stopped, all messages are sent and acknowledged.");
+        }
+        self.report(ServerReport::Drained(summary.clone()));
+        self.drain_summary = Some(summary);
+        self.running = false;
+        if let Some(capture) = &self.capture {
+            capture.flush();
+        }
+    }
+
+    /// Periodic work of the protocol
+    fn on_tick(&mut self, now: Instant) {
+        if self.report_send.is_some() {
+            self.report(ServerReport::Stats(self.senders.stats.clone()));
+        }
+        if let Some(capture) = &self.capture {
+            capture.flush();
+        }
+        self.protocol.on_tick(self.id, &mut self.senders, now);
+    }
+
+    /// Send a report to whoever listens on the report channel
+    fn report(&self, report: ServerReport) {
+        if let Some(report_send) = &self.report_send {
+            if let Err(e) = report_send.send(report) {
+                warn!("WARNING: Could not send report. {}", e);
+            }
+        }
+    }
+
+    /// Process fragment received
+    fn on_fragment(&mut self, routing: Routing, session_id: Session, fragment: Fragment) {
+        info!("Received fragment: {:?}", fragment);
+
+        let src_id = routing.source();
+        match src_id {
+            Some(node_id) => {
+                let des_id = routing.destination();
+                // Acks and nacks travel back along the route the fragment came from
+                let reversed_path = Self::reply_route(self.id, &routing);
+
+                if des_id.is_none() || des_id.is_some_and(|id| id != self.id) {
+                    // Packet is not meant for us
+                    if let Err(e) = Self::send_packet_on_route(
+                        &mut self.senders,
+                        node_id,
+                        reversed_path,
+                        PacketType::Nack(Nack {
+                            fragment_index: fragment.fragment_index,
+                            nack_type: NackType::UnexpectedRecipient(self.id),
+                        }),
+                        session_id,
+                    ) {
+                        warn!("WARNING: Could not send nack. {}", e);
+                    }
+                    return;
+                }
+
+                if self.drain_deadline.is_some() && !self.reassembly.is_known(session_id, node_id) {
+                    // No new messages are accepted after being killed, let the sender know it did not arrive
+                    info!(
+                        "Refusing fragment of new session {} from {} while stopping.",
+                        session_id, node_id
+                    );
+                    if let Err(e) = Self::send_packet_on_route(
+                        &mut self.senders,
+                        node_id,
+                        reversed_path,
+                        PacketType::Nack(Nack {
+                            fragment_index: fragment.fragment_index,
+                            nack_type: NackType::Dropped,
+                        }),
+                        session_id,
+                    ) {
+                        warn!("WARNING: Could not send nack. {}", e);
+                    }
+                    return;
+                }
+
+                // Learn the links the fragment travelled over
+                self.senders.topology.add_route(&routing.hops);
+                self.flush_waiting();
+
+                if let Err(e) = Self::send_packet_on_route(
+                    &mut self.senders,
+                    node_id,
+                    reversed_path,
+                    PacketType::Ack(Ack {
+                        fragment_index: fragment.fragment_index,
+                    }),
+                    session_id,
+                ) {
+                    warn!("WARNING: Could not send ack. {}", e);
+                }
+
+                // Collect fragment parts until the full message is received
+                let fragment_index = fragment.fragment_index;
+                let outcome = self.reassembly.add_fragment(
+                    session_id,
+                    node_id,
+                    fragment,
+                    self.senders.clock.now(),
+                );
+                self.report_evicted();
+                match outcome {
+                    FragmentOutcome::Incomplete => {}
+                    FragmentOutcome::Complete(buffer) => {
+                        match Message::from_fragments(buffer.into_fragments()) {
+                            Ok(message) => {
+                                info!("Fragments parsed to message: {:?}", message);
+                                self.senders.stats.record_message(&message);
+                                self.protocol.on_message(
+                                    self.id,
+                                    &mut self.senders,
+                                    node_id,
+                                    message,
+                                    session_id,
+                                );
+                            }
+                            Err(e) => {
+                                self.senders.stats.reassembly_failures += 1;
+                                warn!("WARNING: Fragments could not be parsed to message. {}", e);
+                            }
+                        };
+                    }
+                    FragmentOutcome::Duplicate => {
+                        info!(
+                            "Ignoring duplicate fragment {}:{} from {}.",
+                            session_id, fragment_index, node_id
+                        );
+                    }
+                    FragmentOutcome::AlreadyCompleted => {
+                        info!(
+                            "Ignoring fragment {}:{} from {}, message was already processed.",
+                            session_id, fragment_index, node_id
+                        );
+                    }
+                    FragmentOutcome::AlreadyRejected => {
+                        info!(
+                            "Ignoring fragment {}:{} from {}, message was already dropped.",
+                            session_id, fragment_index, node_id
+                        );
+                    }
+                    FragmentOutcome::Invalid(reason) => {
+                        self.senders.stats.reassembly_failures += 1;
+                        warn!(
+                            "WARNING: Ignoring invalid fragment {}:{} from {}. {}",
+                            session_id, fragment_index, node_id, reason
+                        );
+                    }
+                    FragmentOutcome::Rejected(reason) => {
+                        self.senders.stats.reassembly_failures += 1;
+                        warn!(
+                            "WARNING: Dropping message {} from {}. {}",
+                            session_id, node_id, reason
+                        );
+                        self.report(ServerReport::MessageDropped {
+                            from: node_id,
+                            session_id,
+                            reason,
+                        });
+                    }
+                }
+            }
+            None => {
+                warn!(
+                    "WARNING: Received packet with invalid routing_header. {}",
+                    routing
+                );
+            }
+        }
+    }
+
+    /// Drop queued messages for which no route was found in time
+    fn expire_outbound(&mut self, now: Instant) {
+        for node_id in self.senders.outbound.expire(now) {
+            warn!(
+                "WARNING: Dropping message to {}, no route found in time.",
+                node_id
+            );
+        }
+    }
+
+    /// Report messages dropped to make room for others being reassembled
+    fn report_evicted(&mut self) {
+        for (session_id, node_id) in self.reassembly.take_evicted() {
+            self.senders.stats.reassembly_failures += 1;
+            warn!(
+                "WARNING: Dropping message {} from {}, too many fragments are being reassembled.",
+                session_id, node_id
+            );
+            self.report(ServerReport::MessageDropped {
+                from: node_id,
+                session_id,
+                reason: String::from("Too many fragments being reassembled"),
+            });
+        }
+    }
+
+    /// Drop messages of which the sender stopped sending fragments
+    fn expire_reassembly(&mut self, now: Instant) {
+        for (session_id, node_id) in self.reassembly.expire(now) {
+            self.senders.stats.reassembly_failures += 1;
+            warn!(
+                "WARNING: Dropping message {} from {}, no fragments received in time.",
+                session_id, node_id
+            );
+            self.report(ServerReport::MessageDropped {
+                from: node_id,
+                session_id,
+                reason: String::from("No fragments received in time"),
+            });
+        }
+    }
+
+    /// Process flood request received
+    fn on_flood_request(&mut self, mut req: FloodRequest) {
+        info!("Received flood request: {:?}", req);
+
+        if req.initiator_id == self.id {
+            // Our own flood request came back around, no need to respond to ourselves
+            return;
+        }
+
+        // Add self to path
+        req.increment(self.id, NodeType::Server);
+
+        // Path to initiator is reverse of path trace
+        let mut path = Routing::with_first_hop(
+            req.path_trace
+                .iter()
+                .cloned()
+                .map(|(id, _)| id)
+                .rev()
+                .collect(),
+        );
+
+        // Handle flood request where the initiator did not add themselves to the path trace
+        if let Some(destination) = path.destination() {
+            if destination != req.initiator_id {
+                path.append_hop(req.initiator_id);
+            }
+        }
+
+        // Learn the nodes and links the flood request travelled over
+        self.senders.topology.add_path_trace(req.path_trace.clone());
+        self.senders.topology.add_route(&path.hops);
+
+        // Send flood response back along the path trace
+        self.senders.session_id = self.senders.session_id.wrapping_add(1);
+        let session_id = self.senders.session_id;
+        if let Err(e) = Self::send_packet_on_route(
+            &mut self.senders,
+            req.initiator_id,
+            path,
+            PacketType::FloodResponse(FloodResponse {
+                flood_id: req.flood_id,
+                path_trace: req.path_trace,
+            }),
+            session_id,
+        ) {
+            warn!("WARNING: Could not send flood response. {}", e);
+        }
+
+        self.flush_waiting();
+    }
+
+    /// Process flood response received
+    fn on_flood_response(&mut self, resp: FloodResponse) {
+        info!("Received flood response: {:?}", resp);
+
+        if resp.path_trace.first().map(|(id, _)| *id) != Some(self.id) {
+            warn!(
+                "WARNING: Received flood response for flood {}, which we did not start.",
+                resp.flood_id
+            );
+            return;
+        }
+
+        // Learn the nodes and links the flood travelled over
+        self.senders.topology.add_path_trace(resp.path_trace);
+
+        self.flush_waiting();
+    }
+
+    /// Send a flood request to all neighbors to discover the network
+    fn start_flood(senders: &mut ServerSenders) {
+        let now = senders.clock.now();
+        if senders.next_flood(now).is_some_and(|wait| !wait.is_zero()) {
+            // A flood was started recently, its responses are still coming in
+            senders.flood_wanted = true;
+            return;
+        }
+        senders.last_flood = Some(now);
+        senders.flood_wanted = false;
+        senders.flood_id = senders.flood_id.wrapping_add(1);
+        senders.session_id = senders.session_id.wrapping_add(1);
+        info!(
+            "Starting flood {} to discover new routes.",
+            senders.flood_id
+        );
+
+        let own_id = senders.topology.own_id();
+        let req = FloodRequest {
+            flood_id: senders.flood_id,
+            initiator_id: own_id,
+            path_trace: vec![(own_id, NodeType::Server)],
+        };
+        for (neighbor_id, channel) in senders.packet_send.iter() {
+            if let Some(e) = Self::send_packet_raw(
+                channel,
+                &senders.controller_send,
+                &mut senders.history,
+                &mut senders.stats,
+                &senders.shortcut,
+                now,
+                Packet::new_flood_request(Routing::empty_route(), senders.session_id, req.clone()),
+            ) {
+                warn!(
+                    "WARNING: Could not send flood request to {}. {}",
+                    neighbor_id, e
+                );
+            }
+        }
+    }
+
+    /// Start the flood that was needed too soon after the last one, if anything still waits for a route
+    fn start_wanted_flood(&mut self, now: Instant) {
+        if !self.senders.flood_wanted
+            || self
+                .senders
+                .next_flood(now)
+                .is_some_and(|wait| !wait.is_zero())
+        {
+            return;
+        }
+        if self.pending_resends.is_empty() && self.senders.outbound.is_empty() {
+            // Responses of the last flood provided the routes
+            self.senders.flood_wanted = false;
+            return;
+        }
+        Self::start_flood(&mut self.senders);
+    }
+
+    /// Process ack received
+    fn on_ack(&mut self, routing: Routing, session_id: Session, ack: Ack) {
+        self.senders.stats.acks_received += 1;
+        // Acks are sent by the destination of the packet
+        let Some(destination) = routing.source() else {
+            warn!("WARNING: Received ack without source.");
+            return;
+        };
+        let key = (destination, session_id, ack.fragment_index);
+        if let Some(sent) = self.senders.history.get(&key) {
+            // Every drone on the route forwarded the packet
+            self.senders
+                .topology
+                .record_forwarded(&sent.packet.routing_header.hops);
+        }
+
+        if !self.senders.history.ack(&key) {
+            warn!("WARNING: Ack received for packet {}:{} from {}, but no such packet is recorded in our send history.", session_id, ack.fragment_index, destination);
+        }
+        self.forget_resend(&key);
+    }
+
+    /// Time until the first unacknowledged fragment should be resent
+    fn next_retransmit(&self, now: Instant) -> Duration {
+        self.senders
+            .history
+            .next_timeout(now)
+            .unwrap_or(self.senders.retransmit.ack_timeout)
+    }
+
+    /// Time until the next fragment should be resent or the wanted flood can be started
+    fn next_timer(&self, now: Instant) -> Duration {
+        let retransmit = self.next_retransmit(now);
+        match self.senders.next_flood(now) {
+            Some(flood) if self.senders.flood_wanted => retransmit.min(flood),
+            _ => retransmit,
+        }
+    }
+
+    /// Resend all fragments of which the ack did not arrive in time
+    fn retransmit_unacked(&mut self, now: Instant) {
+        let timed_out = self.senders.history.timed_out(now);
+
+        for key in timed_out {
+            if self.give_up_exhausted(&key, "no ack received") {
+                continue;
+            }
+            let Some(sent) = self.senders.history.get(&key) else {
+                continue;
+            };
+
+            info!(
+                "Resending packet {}:{} to {} (retry {}), no ack received in time.",
+                key.1,
+                key.2,
+                key.0,
+                sent.retries + 1
+            );
+            // The topology may have changed since the packet was sent, resend along the current route
+            self.resend_or_park(key);
+            self.senders.history.record_retry(&key);
+        }
+    }
+
+    /// Give up on a packet that was resent the maximum amount of times, returns if it was given up
+    fn give_up_exhausted(&mut self, key: &HistoryKey, reason: &str) -> bool {
+        match self.senders.history.get(key) {
+            Some(sent) if sent.retries >= self.senders.retransmit.max_retries => {
+                warn!(
+                    "WARNING: Giving up on packet {}:{} to {}, {} after {} resends.",
+                    key.1, key.2, key.0, reason, sent.retries
+                );
+                self.senders.history.give_up(key);
+                self.forget_resend(key);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Process nack received
+    fn on_nack(&mut self, routing: Routing, session_id: Session, nack: Nack) {
+        self.senders.stats.record_nack(&nack.nack_type);
+        if matches!(nack.nack_type, NackType::Dropped) {
+            match routing.source() {
+                Some(node_id) => self.senders.topology.record_dropped(node_id),
+                None => warn!("WARNING: Received dropped nack without source."),
+            }
+        }
+
+        // Sessions are only unique per destination, use the route of the nack to find the packet
+        let Some(key) =
+            self.senders
+                .history
+                .find_nacked(session_id, nack.fragment_index, &routing.hops)
+        else {
+            warn!("WARNING: Nack received for packet {}:{}, but no such packet is recorded in our send history.", session_id, nack.fragment_index);
+            return;
+        };
+
+        match nack.nack_type {
+            NackType::Dropped => {
+                if self.give_up_exhausted(&key, "dropped every time") {
+                    return;
+                }
+                // Resend the packet that was dropped, along the route that is best by now
+                self.resend_or_park(key);
+                self.senders.history.record_retry(&key);
+            }
+            nack_type => {
+                self.reroute(routing, key, nack_type);
+            }
+        }
+    }
+
+    /// Update the topology with the failed route of a packet and resend it along another route
+    fn reroute(&mut self, nack_routing: Routing, key: HistoryKey, nack_type: NackType) {
+        let Some(sent) = self.senders.history.get(&key) else {
+            return;
+        };
+        let failed_route = sent.packet.routing_header.clone();
+        let Some(destination) = failed_route.destination() else {
+            warn!("WARNING: Invalid route in nacked packet. Destination is None.");
+            return;
+        };
+
+        warn!(
+            "WARNING: Route to {} failed, looking for another route. {}",
+            destination, failed_route
+        );
+        let topology = &mut self.senders.topology;
+        match (nack_type, nack_routing.source()) {
+            (NackType::ErrorInRouting(node_id), Some(source)) => {
+                // Node that sent the nack cannot reach the next node in the route (anymore)
+                topology.remove_edge(source, node_id);
+            }
+            (NackType::UnexpectedRecipient(node_id), _) => {
+                // The nack travelled back from the node the packet arrived at, through the node that forwarded it there
+                match nack_routing.hops.iter().position(|hop| *hop == node_id) {
+                    Some(i) if i + 1 < nack_routing.hops.len() => {
+                        topology.remove_edge(nack_routing.hops[i + 1], node_id);
+                    }
+                    _ => topology.remove_route(&failed_route.hops),
+                }
+            }
+            (NackType::DestinationIsDrone, _) => {
+                topology.set_node_type(destination, NodeType::Drone);
+            }
+            _ => {
+                // Unknown which part of the route is invalid
+                topology.remove_route(&failed_route.hops);
+            }
+        }
+        self.resend_or_park(key);
+    }
+
+    /// Resend a packet along the current route to its destination, or keep it until a route is found
+    fn resend_or_park(&mut self, key: HistoryKey) {
+        let to = key.0;
+        let Some(sent) = self.senders.history.get(&key) else {
+            // Packet was acknowledged or evicted in the meantime
+            return;
+        };
+        let session_id = sent.packet.session_id;
+        let pack_type = sent.packet.pack_type.clone();
+
+        match Self::prepare_node_send(&mut self.senders, to, false) {
+            Ok(prepared_node_send) => {
+                prepared_node_send.stats.resends += 1;
+                if let Some(e) = Self::send_packet_raw(
+                    prepared_node_send.neighbor,
+                    prepared_node_send.controller,
+                    prepared_node_send.history,
+                    prepared_node_send.stats,
+                    prepared_node_send.shortcut,
+                    prepared_node_send.now,
+                    Packet {
+                        routing_header: prepared_node_send.routing.clone(),
+                        session_id,
+                        pack_type,
+                    },
+                ) {
+                    warn!("WARNING: Could not resend packet along new route. {}", e);
+                }
+            }
+            Err(e) => {
+                info!("No route available ({}), waiting for flood responses.", e);
+                self.senders.history.park(&key);
+                let pending = self.pending_resends.entry(to).or_default();
+                let start_flood = pending.is_empty();
+                if !pending.contains(&key) {
+                    pending.push(key);
+                }
+                if start_flood {
+                    Self::start_flood(&mut self.senders);
+                }
+            }
+        }
+    }
+
+    /// Stop waiting for a route for a packet that does not need to be resent anymore
+    fn forget_resend(&mut self, key: &HistoryKey) {
+        if let Some(pending) = self.pending_resends.get_mut(&key.0) {
+            pending.retain(|pending_key| pending_key != key);
+            if pending.is_empty() {
+                self.pending_resends.remove(&key.0);
+            }
+        }
+    }
+
+    /// Stop waiting for a route for packets that were evicted from the history
+    fn forget_stale_resends(&mut self) {
+        let history = &self.senders.history;
+        self.pending_resends.retain(|_, pending| {
+            pending.retain(|key| history.get(key).is_some());
+            !pending.is_empty()
+        });
+    }
+
+    /// Send everything waiting for a route, to all nodes which have a usable route by now
+    fn flush_waiting(&mut self) {
+        self.flush_pending_resends();
+        self.flush_outbound();
+    }
+
+    /// Resend packets waiting for a route, to all nodes which have a usable route by now
+    fn flush_pending_resends(&mut self) {
+        let waiting: Vec<NodeId> = self.pending_resends.keys().cloned().collect();
+        for node_id in waiting {
+            if !self.senders.has_route(node_id) {
+                continue;
+            }
+
+            for key in self.pending_resends.remove(&node_id).unwrap_or_default() {
+                self.resend_or_park(key);
+            }
+        }
+    }
+
+    /// Send queued messages, to all nodes which have a usable route by now
+    fn flush_outbound(&mut self) {
+        for node_id in self.senders.outbound.destinations() {
+            if !self.senders.has_route(node_id) {
+                continue;
+            }
+
+            for queued in self.senders.outbound.take(node_id) {
+                if let Err(e) = Self::send_message(
+                    queued.from,
+                    &mut self.senders,
+                    node_id,
+                    queued.message,
+                    queued.session_id,
+                ) {
+                    warn!(
+                        "WARNING: Could not send queued message to {}. {}",
+                        node_id, e
+                    );
+                }
+            }
+        }
+    }
+
+    /// Start a flood to discover the network
+    pub fn discover(&mut self) {
+        if let Some(capture) = &self.capture {
+            capture.discover();
+        }
+        Self::start_flood(&mut self.senders);
+    }
+
+    pub fn run(&mut self) {
+        // Discover the network before the first request arrives
+        self.discover();
+
+        while self.running {
+            self.update();
+        }
+    }
+
+    /// Gather information required to send a packet to a node
+    fn prepare_node_send(
+        senders: &mut ServerSenders,
+        to: NodeId,
+        increment_session: bool,
+    ) -> Result<PreparedNodeSend, ServerError> {
+        senders.refresh_route(to);
+        match senders.node_path.get_mut(&to) {
+            Some(node_path) => {
+                // All node paths are stored with hop index 1 (ready to be send)
+                if let Some(neighbor_id) = node_path.current_hop() {
+                    match senders.packet_send.get(&neighbor_id) {
+                        Some(channel) => {
+                            if increment_session {
+                                // Skip sessions to this node that are still in use
+                                senders.session_id = senders.session_id.wrapping_add(1);
+                                while senders.history.has_session(to, senders.session_id) {
+                                    senders.session_id = senders.session_id.wrapping_add(1);
+                                }
+                            }
+
+                            Ok(PreparedNodeSend {
+                                routing: node_path,
+                                session: senders.session_id,
+                                neighbor_id,
+                                neighbor: channel,
+                                controller: &senders.controller_send,
+                                history: &mut senders.history,
+                                stats: &mut senders.stats,
+                                shortcut: &senders.shortcut,
+                                now: senders.clock.now(),
+                            })
+                        }
+                        None => Err(ServerError::UnknownNeighbor(neighbor_id)),
+                    }
+                } else {
+                    warn!("WARNING: Invalid node_path route for node_id. Current hop is None.");
+                    Err(ServerError::NoRoute(to))
+                }
+            }
+            None => Err(ServerError::NoRoute(to)),
+        }
+    }
+
+    /// Route back to the source of a received packet, from where it reached us
+    /// The hop index is set by the drones and not trusted, our own position in the route is used instead
+    fn reply_route(id: NodeId, routing: &Routing) -> Routing {
+        let reached = routing
+            .hops
+            .iter()
+            .position(|hop| *hop == id)
+            .unwrap_or(routing.hop_index.min(routing.hops.len().saturating_sub(1)));
+        Routing::with_first_hop(
+            routing
+                .hops
+                .iter()
+                .take(reached + 1)
+                .rev()
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// Send a (sugared) packet to a node along a fixed route (instead of the route computed from the topology)
+    fn send_packet_on_route(
+        senders: &mut ServerSenders,
+        to: NodeId,
+        route: Routing,
+        packet: PacketType,
+        session_id: Session,
+    ) -> Result<(), ServerError> {
+        let Some(neighbor_id) = route.current_hop() else {
+            warn!("WARNING: Invalid route for node_id. Current hop is None.");
+            return Err(ServerError::NoRoute(to));
+        };
+        let Some(channel) = senders.packet_send.get(&neighbor_id) else {
+            return Err(ServerError::UnknownNeighbor(neighbor_id));
+        };
+
+        match Self::send_packet_raw(
+            channel,
+            &senders.controller_send,
+            &mut senders.history,
+            &mut senders.stats,
+            &senders.shortcut,
+            senders.clock.now(),
+            Packet {
+                routing_header: route,
+                session_id,
+                pack_type: packet,
+            },
+        ) {
+            Some(_) => Err(ServerError::Disconnected(neighbor_id)),
+            None => Ok(()),
+        }
+    }
+
+    /// Send a packet (including session and routing information) to a node
+    fn send_packet_raw(
+        to: &dyn PacketTransport,
+        controller: &Sender<LeafEvent>,
+        history: &mut PacketHistory,
+        stats: &mut ServerStats,
+        shortcut: &ShortcutPolicy,
+        now: Instant,
+        packet: Packet,
+    ) -> Option<SendError<Packet>> {
+        // Record any packet that can be required to resend
+        // Only MsgFragments can be dropped
+        let record: bool = matches!(packet.pack_type, PacketType::MsgFragment(_));
+        if record {
+            history.insert(packet.clone(), now);
+            stats.fragments_sent += 1;
+        }
+
+        // Inform the controller we are sending a packet
+        if let Err(e) = controller.send(LeafEvent::PacketSend(packet.clone())) {
+            warn!("WARNING: Could not inform controller of packet send: {}", e)
+        }
+
+        // Try sending the packet to the closest neighbor
+        let send_error = to.send(packet.clone()).err();
+
+        // Are we allowed to use the Simulation Controller in case we cannot send it through our neighbor
+        let use_shortcut = shortcut.allows(&packet.pack_type);
+        if use_shortcut && send_error.is_some() {
+            let shortcut_error = controller.send(LeafEvent::ControllerShortcut(packet)).err();
+
+            return match shortcut_error {
+                Some(e) => {
+                    warn!("WARNING: Send error using shortcut: {}", e);
+                    send_error // Return original send error
+                }
+                None => {
+                    stats.shortcuts += 1;
+                    None // Hide original error, sending through Simulation Controller succeeded
+                }
+            };
+        }
+
+        send_error
+    }
+
+    /// Send a message to a node
+    /// When no route to the node is known, the message is queued and a flood is started to discover one
+    pub fn send_message(
+        from: NodeId,
+        senders: &mut ServerSenders,
+        to: NodeId,
+        message: Message,
+        fixed_session: Option<u64>, // Session id to use (in case of a response to received packet)
+    ) -> Result<(), ServerError> {
+        if !senders.has_route(to) {
+            // No route to the node is known, keep the message until one is discovered
+            if !senders
+                .outbound
+                .push(to, from, message, fixed_session, senders.clock.now())
+            {
+                return Err(ServerError::QueueFull(to));
+            }
+            info!("No route to {}, message is queued until one is found.", to);
+            Self::start_flood(senders);
+            return Ok(());
+        }
+
+        Self::send_message_raw(from, senders, to, message, fixed_session)
+    }
+
+    /// Send a message to a node
+    /// The message will be split in multiple fragments
+    /// More optimized than using send_packet for each fragment
+    fn send_message_raw(
+        from: NodeId,
+        senders: &mut ServerSenders,
+        to: NodeId,
+        message: Message,
+        fixed_session: Option<u64>, // Session id to use (in case of a response to received packet)
+    ) -> Result<(), ServerError> {
+        let fragments = message.clone().into_fragments();
+        if fragments.is_empty() {
+            return Err(ServerError::Fragmentation);
+        }
+
+        let prepared_node_send = Self::prepare_node_send(senders, to, fixed_session.is_none())?;
+        let session = fixed_session.unwrap_or(prepared_node_send.session);
+
+        // Inform controller we are sending a message
+        if let Err(e) = prepared_node_send
+            .controller
+            .send(LeafEvent::MessageStartSend {
+                start: from,
+                session,
+                dest: to,
+                message,
+            })
+        {
+            warn!("WARNING: Could not send message start to controller: {}", e);
+        }
+
+        // Send message split into fragment packets
+        let mut send_failed = false;
+        for fragment in fragments {
+            prepared_node_send.stats.bytes_served += fragment.length as u64;
+            if let Some(e) = Self::send_packet_raw(
+                prepared_node_send.neighbor,
+                prepared_node_send.controller,
+                prepared_node_send.history,
+                prepared_node_send.stats,
+                prepared_node_send.shortcut,
+                prepared_node_send.now,
+                Packet::new_fragment(prepared_node_send.routing.clone(), session, fragment),
+            ) {
+                warn!("WARNING: Send message error: {}", e);
+                send_failed = true;
+            }
+        }
+
+        // Inform controller finished sending a message
+        if let Err(e) = prepared_node_send
+            .controller
+            .send(LeafEvent::MessageFullySent(from, session))
+        {
+            warn!(
+                "WARNING: Could not send message fully sent to controller: {}",
+                e
+            );
+        }
+
+        if send_failed {
+            return Err(ServerError::Disconnected(prepared_node_send.neighbor_id));
+        }
+        Ok(())
+    }
+}
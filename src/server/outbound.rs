@@ -0,0 +1,121 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use common_structs::{message::Message, types::Session};
+use wg_2024::network::NodeId;
+
+/// Message waiting for a route to its destination
+pub struct QueuedMessage {
+    pub from: NodeId,
+    pub message: Message,
+    /// Session id to use (in case of a response to received packet)
+    pub session_id: Option<Session>,
+    queued_at: Instant,
+}
+
+/// Limits on the messages waiting for a route
+#[derive(Debug, Clone)]
+pub struct OutboundLimits {
+    /// Time after which a waiting message is dropped
+    pub ttl: Duration,
+    /// Maximum amount of messages waiting for the same destination
+    pub max_per_destination: usize,
+    /// Maximum amount of messages waiting for all destinations together
+    pub max_total: usize,
+}
+
+impl Default for OutboundLimits {
+    fn default() -> Self {
+        OutboundLimits {
+            ttl: Duration::from_secs(30),
+            max_per_destination: 64,
+            max_total: 1024,
+        }
+    }
+}
+
+/// Messages that could not be sent yet because no route to their destination is known
+#[derive(Default)]
+pub struct OutboundQueue {
+    limits: OutboundLimits,
+    /// Per destination, the waiting messages (oldest first)
+    queues: HashMap<NodeId, VecDeque<QueuedMessage>>,
+    total: usize,
+}
+
+impl OutboundQueue {
+    pub fn new(limits: OutboundLimits) -> Self {
+        OutboundQueue {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    /// Amount of messages waiting for a route
+    pub fn len(&self) -> usize {
+        self.total
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+
+    /// Queue a message until a route to `to` is known, returns false if the queue is full
+    pub fn push(
+        &mut self,
+        to: NodeId,
+        from: NodeId,
+        message: Message,
+        session_id: Option<Session>,
+        now: Instant,
+    ) -> bool {
+        if self.total >= self.limits.max_total {
+            return false;
+        }
+        let queue = self.queues.entry(to).or_default();
+        if queue.len() >= self.limits.max_per_destination {
+            return false;
+        }
+
+        queue.push_back(QueuedMessage {
+            from,
+            message,
+            session_id,
+            queued_at: now,
+        });
+        self.total += 1;
+        true
+    }
+
+    /// All destinations with messages waiting
+    pub fn destinations(&self) -> Vec<NodeId> {
+        self.queues.keys().cloned().collect()
+    }
+
+    /// Remove all messages waiting for a destination, oldest first
+    pub fn take(&mut self, to: NodeId) -> VecDeque<QueuedMessage> {
+        let queue = self.queues.remove(&to).unwrap_or_default();
+        self.total -= queue.len();
+        queue
+    }
+
+    /// Drop all messages that waited longer than the ttl, returns their destinations
+    pub fn expire(&mut self, now: Instant) -> Vec<NodeId> {
+        let ttl = self.limits.ttl;
+        let mut expired = Vec::new();
+        self.queues.retain(|to, queue| {
+            while queue
+                .front()
+                .is_some_and(|queued| now.saturating_duration_since(queued.queued_at) >= ttl)
+            {
+                queue.pop_front();
+                expired.push(*to);
+            }
+            !queue.is_empty()
+        });
+        self.total -= expired.len();
+        expired
+    }
+}
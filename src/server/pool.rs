@@ -0,0 +1,161 @@
+use std::{
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use common_structs::{message::Message, types::Session};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use log::warn;
+use wg_2024::network::NodeId;
+
+use super::{ServerProtocol, ServerSenders};
+
+/// Message to send in response to a request
+pub struct Reply {
+    pub to: NodeId,
+    pub message: Message,
+    /// Session id to use (in case of a response to received packet)
+    pub session_id: Option<Session>,
+}
+
+impl Reply {
+    /// Response to the sender of a request, using the session of the request
+    pub fn response(to: NodeId, message: Message, session_id: Session) -> Self {
+        Reply {
+            to,
+            message,
+            session_id: Some(session_id),
+        }
+    }
+}
+
+/// Protocol of which the requests can be handled on any thread, without access to the network
+pub trait ConcurrentProtocol: Send + Sync + 'static {
+    fn handle(
+        &self,
+        server: NodeId,
+        from: NodeId,
+        message: Message,
+        session_id: Session,
+    ) -> Vec<Reply>;
+}
+
+/// Request to handle on a worker
+struct Job {
+    server: NodeId,
+    from: NodeId,
+    message: Message,
+    session_id: Session,
+}
+
+/// Counts a job as finished once dropped, also when handling it panicked
+struct InFlightGuard<'a>(&'a AtomicUsize);
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Handles the requests of a protocol on a pool of worker threads
+/// The replies are sent by the network loop, so a slow request does not block acks, nacks and floods
+pub struct WorkerPool<P: ConcurrentProtocol> {
+    jobs: Option<Sender<Job>>,
+    /// Jobs handed to the workers of which the replies are not sent yet
+    in_flight: Arc<AtomicUsize>,
+    replies: Receiver<Reply>,
+    workers: Vec<JoinHandle<()>>,
+    _protocol: PhantomData<P>,
+}
+
+impl<P: ConcurrentProtocol> WorkerPool<P> {
+    pub fn new(protocol: P, worker_count: usize) -> Self {
+        let protocol = Arc::new(protocol);
+        let (jobs_send, jobs_recv) = unbounded::<Job>();
+        let (replies_send, replies_recv) = unbounded::<Reply>();
+        let in_flight = Arc::new(AtomicUsize::new(0));
+
+        let workers = (0..worker_count.max(1))
+            .map(|_| {
+                let protocol = protocol.clone();
+                let jobs_recv = jobs_recv.clone();
+                let replies_send = replies_send.clone();
+                let in_flight = in_flight.clone();
+                thread::spawn(move || {
+                    // Stops when the pool is dropped
+                    for job in jobs_recv.iter() {
+                        // Dropped only after the replies are sent, so they are never missed when waiting for the pool
+                        let _in_flight = InFlightGuard(&in_flight);
+                        let replies =
+                            protocol.handle(job.server, job.from, job.message, job.session_id);
+                        for reply in replies {
+                            if replies_send.send(reply).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        WorkerPool {
+            jobs: Some(jobs_send),
+            in_flight,
+            replies: replies_recv,
+            workers,
+            _protocol: PhantomData,
+        }
+    }
+}
+
+impl<P: ConcurrentProtocol> ServerProtocol for WorkerPool<P> {
+    fn on_message(
+        &mut self,
+        server: NodeId,
+        _senders: &mut ServerSenders,
+        from: NodeId,
+        message: Message,
+        session_id: Session,
+    ) {
+        let job = Job {
+            server,
+            from,
+            message,
+            session_id,
+        };
+        match &self.jobs {
+            Some(jobs) => {
+                self.in_flight.fetch_add(1, Ordering::SeqCst);
+                if let Err(e) = jobs.send(job) {
+                    self.in_flight.fetch_sub(1, Ordering::SeqCst);
+                    warn!("WARNING: Could not hand request to worker pool. {}", e);
+                }
+            }
+            None => warn!("WARNING: Worker pool is stopped."),
+        }
+    }
+
+    fn replies(&self) -> Option<Receiver<Reply>> {
+        Some(self.replies.clone())
+    }
+
+    fn pending_requests(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+}
+
+impl<P: ConcurrentProtocol> Drop for WorkerPool<P> {
+    fn drop(&mut self) {
+        // Closing the job channel stops the workers once they finished their current job
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                warn!("WARNING: Worker panicked while handling a request.");
+            }
+        }
+    }
+}
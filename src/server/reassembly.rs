@@ -0,0 +1,418 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::{Duration, Instant},
+};
+
+use common_structs::types::{FragmentIdx, Session};
+use wg_2024::{
+    network::NodeId,
+    packet::{Fragment, FRAGMENT_DSIZE},
+};
+
+/// Amount of completed (or rejected) sessions remembered to recognise retransmissions
+const MAX_FINISHED_SESSIONS: usize = 1024;
+
+/// Amount of fragments stored together, memory for a block is allocated by its first fragment
+const FRAGMENTS_PER_BLOCK: usize = 8;
+const BLOCK_SIZE: usize = FRAGMENTS_PER_BLOCK * FRAGMENT_DSIZE;
+
+/// Data of a fragment that was not received
+const EMPTY_FRAGMENT: [u8; FRAGMENT_DSIZE] = [0; FRAGMENT_DSIZE];
+
+/// Session id + sender of a message
+pub type ReassemblyKey = (Session, NodeId);
+
+/// What happened with a fragment that was added
+pub enum FragmentOutcome {
+    /// More fragments are required to complete the message
+    Incomplete,
+    /// All fragments of the message are received
+    Complete(ReassemblyBuffer),
+    /// Fragment with this index was already received
+    Duplicate,
+    /// Fragment belongs to a message that was already completed
+    AlreadyCompleted,
+    /// Fragment belongs to a message that was already rejected
+    AlreadyRejected,
+    /// Fragment does not fit the message
+    Invalid(String),
+    /// Fragment would exceed the reassembly limits, the message is dropped
+    Rejected(String),
+}
+
+/// Limits on the messages being reassembled, protecting against senders that never finish
+#[derive(Debug, Clone)]
+pub struct ReassemblyLimits {
+    /// Time without new fragments after which a message is abandoned
+    pub timeout: Duration,
+    /// Maximum amount of messages being reassembled per sender
+    pub max_sessions_per_node: usize,
+    /// Maximum amount of fragments of a single message
+    pub max_fragments_per_message: u64,
+    /// Maximum amount of fragments received for all messages being reassembled together
+    pub max_total_fragments: u64,
+}
+
+impl Default for ReassemblyLimits {
+    fn default() -> Self {
+        ReassemblyLimits {
+            timeout: Duration::from_secs(10),
+            max_sessions_per_node: 16,
+            max_fragments_per_message: 65536,
+            max_total_fragments: 131072,
+        }
+    }
+}
+
+/// Data of a message being reassembled, every fragment is written to its place as it arrives.
+/// Memory grows with the fragments received, not with the size the sender announces.
+pub struct ReassemblyBuffer {
+    /// Data of the fragments in blocks of FRAGMENTS_PER_BLOCK, None until a fragment of the block is received
+    blocks: Vec<Option<Box<[u8; BLOCK_SIZE]>>>,
+    /// Bitmap of the fragments that are received
+    received: Vec<u64>,
+    received_count: u64,
+    total: u64,
+    /// Length of the data in the last fragment (once received)
+    last_length: usize,
+}
+
+impl ReassemblyBuffer {
+    /// Create the buffer for a message of `total` fragments, None if it does not fit in memory
+    pub fn new(total: u64) -> Option<Self> {
+        let fragment_count = usize::try_from(total).ok()?;
+        fragment_count.checked_mul(FRAGMENT_DSIZE)?;
+        Some(ReassemblyBuffer {
+            blocks: Vec::new(),
+            received: vec![0; fragment_count.div_ceil(64)],
+            received_count: 0,
+            total,
+            last_length: 0,
+        })
+    }
+
+    /// Amount of fragments of the message
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Amount of fragments received so far
+    pub fn received(&self) -> u64 {
+        self.received_count
+    }
+
+    pub fn contains(&self, fragment_index: FragmentIdx) -> bool {
+        fragment_index < self.total
+            && self.received[(fragment_index / 64) as usize] & (1 << (fragment_index % 64)) != 0
+    }
+
+    /// Write the data of a fragment in place, returns false if the fragment does not fit or was already received
+    pub fn insert(&mut self, fragment: &Fragment) -> bool {
+        if fragment.fragment_index >= self.total || self.contains(fragment.fragment_index) {
+            return false;
+        }
+
+        let index = fragment.fragment_index as usize;
+        let length = (fragment.length as usize).min(FRAGMENT_DSIZE);
+        let block_index = index / FRAGMENTS_PER_BLOCK;
+        if self.blocks.len() <= block_index {
+            self.blocks.resize_with(block_index + 1, || None);
+        }
+        let block = self.blocks[block_index].get_or_insert_with(|| Box::new([0; BLOCK_SIZE]));
+        let start = (index % FRAGMENTS_PER_BLOCK) * FRAGMENT_DSIZE;
+        block[start..start + length].copy_from_slice(&fragment.data[..length]);
+        if fragment.fragment_index == self.total - 1 {
+            self.last_length = length;
+        }
+
+        self.received[index / 64] |= 1 << (index % 64);
+        self.received_count += 1;
+        true
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.received_count == self.total
+    }
+
+    /// Memory used for the data and bitmap of the message
+    pub fn allocated_bytes(&self) -> usize {
+        let allocated_blocks = self.blocks.iter().filter(|block| block.is_some()).count();
+        self.blocks.capacity() * std::mem::size_of::<Option<Box<[u8; BLOCK_SIZE]>>>()
+            + allocated_blocks * BLOCK_SIZE
+            + self.received.capacity() * std::mem::size_of::<u64>()
+    }
+
+    /// Data of a fragment, zeroes if it was not received
+    fn fragment_data(&self, index: usize) -> &[u8] {
+        let length = if index as u64 == self.total - 1 {
+            self.last_length
+        } else {
+            FRAGMENT_DSIZE
+        };
+        match self.blocks.get(index / FRAGMENTS_PER_BLOCK) {
+            Some(Some(block)) => {
+                let start = (index % FRAGMENTS_PER_BLOCK) * FRAGMENT_DSIZE;
+                &block[start..start + length]
+            }
+            _ => &EMPTY_FRAGMENT[..length],
+        }
+    }
+
+    /// Data of the full message
+    pub fn into_bytes(self) -> Vec<u8> {
+        let length = match self.total {
+            0 => 0,
+            total => (total as usize - 1) * FRAGMENT_DSIZE + self.last_length,
+        };
+        let mut bytes = Vec::with_capacity(length);
+        for index in 0..self.total as usize {
+            bytes.extend_from_slice(self.fragment_data(index));
+        }
+        bytes
+    }
+
+    /// Fragments of the full message, as messages can only be parsed from fragments.
+    /// This copies every fragment out of the blocks once more, so parsing still costs a copy of the message.
+    pub fn into_fragments(self) -> Vec<Fragment> {
+        (0..self.total as usize)
+            .map(|index| {
+                let chunk = self.fragment_data(index);
+                let mut data = [0; FRAGMENT_DSIZE];
+                data[..chunk.len()].copy_from_slice(chunk);
+                Fragment {
+                    fragment_index: index as u64,
+                    total_n_fragments: self.total,
+                    length: chunk.len() as u8,
+                    data,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Message of which not all fragments are received yet
+struct PendingMessage {
+    buffer: ReassemblyBuffer,
+    /// Time the last new fragment was received
+    last_fragment_at: Instant,
+}
+
+/// Sessions that were finished recently, forgetting the oldest one if too many are remembered
+#[derive(Default)]
+struct FinishedSessions {
+    sessions: HashSet<ReassemblyKey>,
+    /// Order in which the sessions were finished, oldest first
+    order: VecDeque<ReassemblyKey>,
+}
+
+impl FinishedSessions {
+    fn contains(&self, key: &ReassemblyKey) -> bool {
+        self.sessions.contains(key)
+    }
+
+    fn insert(&mut self, key: ReassemblyKey) {
+        if !self.sessions.insert(key) {
+            return;
+        }
+        self.order.push_back(key);
+        if self.order.len() > MAX_FINISHED_SESSIONS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.sessions.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Collects the fragments of messages until they are complete
+#[derive(Default)]
+pub struct Reassembly {
+    limits: ReassemblyLimits,
+    /// Per session id + sender, the fragments received so far
+    pending: HashMap<ReassemblyKey, PendingMessage>,
+    /// Per sender, the amount of messages being reassembled
+    sessions_per_node: HashMap<NodeId, usize>,
+    /// Amount of fragments received for all messages being reassembled together
+    total_fragments: u64,
+    /// Sessions which were completed recently
+    completed: FinishedSessions,
+    /// Sessions which were rejected recently, so every message is reported dropped only once
+    rejected: FinishedSessions,
+    /// Messages dropped to make room for other messages, not taken yet
+    evicted: Vec<ReassemblyKey>,
+}
+
+impl Reassembly {
+    pub fn new(limits: ReassemblyLimits) -> Self {
+        Reassembly {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    /// Amount of messages being reassembled
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Whether fragments of this session were received before (still being reassembled or completed)
+    pub fn is_known(&self, session_id: Session, from: NodeId) -> bool {
+        let key = (session_id, from);
+        self.pending.contains_key(&key) || self.completed.contains(&key)
+    }
+
+    pub fn add_fragment(
+        &mut self,
+        session_id: Session,
+        from: NodeId,
+        fragment: Fragment,
+        now: Instant,
+    ) -> FragmentOutcome {
+        let key = (session_id, from);
+        if self.completed.contains(&key) {
+            return FragmentOutcome::AlreadyCompleted;
+        }
+        if self.rejected.contains(&key) {
+            return FragmentOutcome::AlreadyRejected;
+        }
+        if fragment.fragment_index >= fragment.total_n_fragments {
+            return FragmentOutcome::Invalid(format!(
+                "Fragment index {} is not below the total of {} fragments",
+                fragment.fragment_index, fragment.total_n_fragments
+            ));
+        }
+        if fragment.total_n_fragments > self.limits.max_fragments_per_message {
+            self.reject(key);
+            return FragmentOutcome::Rejected(format!(
+                "Message of {} fragments exceeds the maximum of {} fragments",
+                fragment.total_n_fragments, self.limits.max_fragments_per_message
+            ));
+        }
+
+        if !self.pending.contains_key(&key) {
+            let sessions = self.sessions_per_node.get(&from).cloned().unwrap_or(0);
+            if sessions >= self.limits.max_sessions_per_node {
+                self.reject(key);
+                return FragmentOutcome::Rejected(format!(
+                    "Sender already has {} messages being reassembled",
+                    sessions
+                ));
+            }
+            let Some(buffer) = ReassemblyBuffer::new(fragment.total_n_fragments) else {
+                self.reject(key);
+                return FragmentOutcome::Rejected(format!(
+                    "Message of {} fragments does not fit in memory",
+                    fragment.total_n_fragments
+                ));
+            };
+
+            self.pending.insert(
+                key,
+                PendingMessage {
+                    buffer,
+                    last_fragment_at: now,
+                },
+            );
+            *self.sessions_per_node.entry(from).or_insert(0) += 1;
+        }
+
+        let Some(message) = self.pending.get(&key) else {
+            return FragmentOutcome::Incomplete;
+        };
+        if message.buffer.total() != fragment.total_n_fragments {
+            return FragmentOutcome::Invalid(format!(
+                "Total of {} fragments does not match earlier total of {}",
+                fragment.total_n_fragments,
+                message.buffer.total()
+            ));
+        }
+        if message.buffer.contains(fragment.fragment_index) {
+            return FragmentOutcome::Duplicate;
+        }
+        // Only fragments actually received are charged, so announcing a large message reserves nothing.
+        // Messages that stopped receiving fragments the longest ago make room for the ones still arriving.
+        while self.total_fragments >= self.limits.max_total_fragments {
+            let Some(oldest) = self.least_recent(&key) else {
+                self.reject(key);
+                return FragmentOutcome::Rejected(format!(
+                    "Maximum of {} fragments being reassembled is reached",
+                    self.limits.max_total_fragments
+                ));
+            };
+            self.reject(oldest);
+            self.evicted.push(oldest);
+        }
+
+        let Some(message) = self.pending.get_mut(&key) else {
+            return FragmentOutcome::Incomplete;
+        };
+        if !message.buffer.insert(&fragment) {
+            return FragmentOutcome::Duplicate;
+        }
+        self.total_fragments += 1;
+        message.last_fragment_at = now;
+        if !message.buffer.is_complete() {
+            return FragmentOutcome::Incomplete;
+        }
+
+        match self.remove(&key) {
+            Some(message) => {
+                self.completed.insert(key);
+                FragmentOutcome::Complete(message.buffer)
+            }
+            None => FragmentOutcome::Incomplete,
+        }
+    }
+
+    /// Messages that were dropped to stay within the total amount of fragments since the last call
+    pub fn take_evicted(&mut self) -> Vec<ReassemblyKey> {
+        std::mem::take(&mut self.evicted)
+    }
+
+    /// Drop all messages that did not receive a new fragment in time, returns the dropped messages
+    pub fn expire(&mut self, now: Instant) -> Vec<ReassemblyKey> {
+        let expired: Vec<ReassemblyKey> = self
+            .pending
+            .iter()
+            .filter(|(_, message)| {
+                now.saturating_duration_since(message.last_fragment_at) >= self.limits.timeout
+            })
+            .map(|(key, _)| *key)
+            .collect();
+
+        for key in expired.iter() {
+            self.remove(key);
+        }
+        expired
+    }
+
+    fn remove(&mut self, key: &ReassemblyKey) -> Option<PendingMessage> {
+        let message = self.pending.remove(key)?;
+        self.total_fragments -= message.buffer.received();
+        if let Some(sessions) = self.sessions_per_node.get_mut(&key.1) {
+            *sessions -= 1;
+            if *sessions == 0 {
+                self.sessions_per_node.remove(&key.1);
+            }
+        }
+        Some(message)
+    }
+
+    /// Message other than the given one that received its last fragment the longest ago
+    fn least_recent(&self, except: &ReassemblyKey) -> Option<ReassemblyKey> {
+        self.pending
+            .iter()
+            .filter(|(key, _)| *key != except)
+            .min_by_key(|(_, message)| message.last_fragment_at)
+            .map(|(key, _)| *key)
+    }
+
+    /// Drop a message that exceeds the limits, later fragments of it are recognised and ignored
+    fn reject(&mut self, key: ReassemblyKey) {
+        self.remove(&key);
+        self.rejected.insert(key);
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use common_structs::{message::Message, types::Session};
+use wg_2024::{network::NodeId, packet::NackType};
+
+use super::DrainSummary;
+
+/// Nacks received, per nack type
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NackCounts {
+    pub error_in_routing: u64,
+    pub destination_is_drone: u64,
+    pub dropped: u64,
+    pub unexpected_recipient: u64,
+}
+
+/// Counters of everything the server did since it started
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerStats {
+    /// Per message variant (e.g. "ReqFile"), the amount of messages received
+    pub messages_received: HashMap<&'static str, u64>,
+    /// Fragments sent, including resends
+    pub fragments_sent: u64,
+    pub acks_received: u64,
+    pub nacks_received: NackCounts,
+    /// Fragments resent after a nack or a missing ack
+    pub resends: u64,
+    /// Packets sent through the controller because the neighbor could not be reached
+    pub shortcuts: u64,
+    /// Messages that could not be reassembled (invalid, rejected, expired or unparsable)
+    pub reassembly_failures: u64,
+    /// Bytes of all messages sent (not counting resends)
+    pub bytes_served: u64,
+}
+
+/// Sent on the report channel of a server, e.g. to the simulation controller
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerReport {
+    /// Snapshot of the statistics, sent on every tick
+    Stats(ServerStats),
+    /// Message being reassembled was dropped, because it exceeded a limit or stopped receiving fragments
+    MessageDropped {
+        from: NodeId,
+        session_id: Session,
+        reason: String,
+    },
+    /// Server stopped after being killed
+    Drained(DrainSummary),
+}
+
+impl ServerStats {
+    pub fn record_message(&mut self, message: &Message) {
+        *self
+            .messages_received
+            .entry(variant_name(message))
+            .or_default() += 1;
+    }
+
+    pub fn record_nack(&mut self, nack_type: &NackType) {
+        let counter = match nack_type {
+            NackType::ErrorInRouting(_) => &mut self.nacks_received.error_in_routing,
+            NackType::DestinationIsDrone => &mut self.nacks_received.destination_is_drone,
+            NackType::Dropped => &mut self.nacks_received.dropped,
+            NackType::UnexpectedRecipient(_) => &mut self.nacks_received.unexpected_recipient,
+        };
+        *counter += 1;
+    }
+}
+
+/// Name of the variant of a message, without its content
+fn variant_name(message: &Message) -> &'static str {
+    match message {
+        Message::ReqServerType => "ReqServerType",
+        Message::ReqFilesList => "ReqFilesList",
+        Message::ReqFile(..) => "ReqFile",
+        Message::ReqMedia(..) => "ReqMedia",
+        Message::ReqChatRegistration => "ReqChatRegistration",
+        Message::ReqChatClients => "ReqChatClients",
+        Message::ReqChatSend { .. } => "ReqChatSend",
+        Message::RespServerType(..) => "RespServerType",
+        Message::RespFilesList(..) => "RespFilesList",
+        Message::RespFile(..) => "RespFile",
+        Message::RespMedia(..) => "RespMedia",
+        Message::RespClientList(..) => "RespClientList",
+        Message::RespChatFrom { .. } => "RespChatFrom",
+        Message::ErrUnsupportedRequestType => "ErrUnsupportedRequestType",
+        Message::ErrNotFound => "ErrNotFound",
+        Message::ErrNotExistentClient => "ErrNotExistentClient",
+    }
+}
@@ -0,0 +1,290 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap},
+};
+
+use common_structs::types::Routing;
+use wg_2024::{network::NodeId, packet::NodeType};
+
+use super::RoutingStrategy;
+
+/// Drops assumed before any packet is observed, so unknown drones are not seen as perfect
+const PRIOR_DROPPED: f64 = 1.0;
+/// Packets assumed before any packet is observed
+const PRIOR_PACKETS: f64 = 10.0;
+/// Highest drop rate used for routing, to keep the cost of a drone finite
+const MAX_DROP_RATE: f64 = 0.99;
+/// Change of the estimated drop rate of a drone after which routes are recomputed
+const REROUTE_DROP_RATE_CHANGE: f64 = 0.05;
+
+/// Packets observed passing through a drone
+#[derive(Debug, Clone, Default)]
+struct DropCount {
+    forwarded: u64,
+    dropped: u64,
+}
+
+/// Graph of the network as far as it is known to the server
+pub struct Topology {
+    own_id: NodeId,
+    /// Per node, its type (if known)
+    node_types: HashMap<NodeId, NodeType>,
+    /// Per node, the nodes it is connected to (ordered to make route selection deterministic)
+    edges: BTreeMap<NodeId, BTreeSet<NodeId>>,
+    /// Per drone, the packets it forwarded and dropped
+    drops: HashMap<NodeId, DropCount>,
+    /// Per drone, the estimated drop rate when routes were last recomputed because of it
+    routed_drop_rates: HashMap<NodeId, f64>,
+    /// Incremented on every change of the graph (or a large change of a drop rate)
+    version: u64,
+    strategy: RoutingStrategy,
+}
+
+impl Topology {
+    pub fn new(own_id: NodeId) -> Self {
+        let mut node_types = HashMap::new();
+        node_types.insert(own_id, NodeType::Server);
+        Topology {
+            own_id,
+            node_types,
+            edges: BTreeMap::new(),
+            drops: HashMap::new(),
+            routed_drop_rates: HashMap::new(),
+            version: 0,
+            strategy: RoutingStrategy::default(),
+        }
+    }
+
+    pub fn set_strategy(&mut self, strategy: RoutingStrategy) {
+        if self.strategy != strategy {
+            self.strategy = strategy;
+            self.version += 1;
+        }
+    }
+
+    pub fn own_id(&self) -> NodeId {
+        self.own_id
+    }
+
+    /// Changes every time the graph changes, routes computed for an older version might be outdated
+    /// Small changes of the drop rates do not count, recomputing every route on every ack would be too expensive
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn add_edge(&mut self, a: NodeId, b: NodeId) {
+        if a == b {
+            return;
+        }
+
+        let added_a = self.edges.entry(a).or_default().insert(b);
+        let added_b = self.edges.entry(b).or_default().insert(a);
+        if added_a || added_b {
+            self.version += 1;
+        }
+    }
+
+    pub fn remove_edge(&mut self, a: NodeId, b: NodeId) {
+        let removed_a = self.edges.get_mut(&a).is_some_and(|edges| edges.remove(&b));
+        let removed_b = self.edges.get_mut(&b).is_some_and(|edges| edges.remove(&a));
+        if removed_a || removed_b {
+            self.version += 1;
+        }
+    }
+
+    pub fn set_node_type(&mut self, node_id: NodeId, node_type: NodeType) {
+        if self.node_types.get(&node_id) != Some(&node_type) {
+            self.node_types.insert(node_id, node_type);
+            self.version += 1;
+        }
+    }
+
+    /// Add all nodes and links in the path trace of a flood
+    pub fn add_path_trace(&mut self, path_trace: Vec<(NodeId, NodeType)>) {
+        let hops: Vec<NodeId> = path_trace.iter().map(|(id, _)| *id).collect();
+        for (node_id, node_type) in path_trace {
+            if node_id != self.own_id {
+                self.set_node_type(node_id, node_type);
+            }
+        }
+        for link in hops.windows(2) {
+            self.add_edge(link[0], link[1]);
+        }
+    }
+
+    /// Add all links in the route of a received packet, every node between both ends is a drone
+    pub fn add_route(&mut self, hops: &[NodeId]) {
+        if hops.len() > 2 {
+            for node_id in hops[1..hops.len() - 1].iter() {
+                // A malformed route can pass through us, we are never a drone
+                if *node_id != self.own_id {
+                    self.set_node_type(*node_id, NodeType::Drone);
+                }
+            }
+        }
+        for link in hops.windows(2) {
+            self.add_edge(link[0], link[1]);
+        }
+    }
+
+    /// Remove the links of a route that turned out to be invalid, except our own link to the first hop
+    pub fn remove_route(&mut self, hops: &[NodeId]) {
+        for link in hops.windows(2).skip(1) {
+            self.remove_edge(link[0], link[1]);
+        }
+    }
+
+    /// Record that a packet was delivered along a route, all drones in it forwarded the packet
+    pub fn record_forwarded(&mut self, hops: &[NodeId]) {
+        if hops.len() > 2 {
+            for node_id in hops[1..hops.len() - 1].iter() {
+                self.drops.entry(*node_id).or_default().forwarded += 1;
+                self.update_estimate(*node_id);
+            }
+        }
+    }
+
+    /// Record that a drone dropped a packet
+    pub fn record_dropped(&mut self, node_id: NodeId) {
+        self.drops.entry(node_id).or_default().dropped += 1;
+        self.update_estimate(node_id);
+    }
+
+    /// Routes are only recomputed once the drop rate of a drone changed noticeably
+    fn update_estimate(&mut self, node_id: NodeId) {
+        if self.strategy == RoutingStrategy::FewestHops {
+            return;
+        }
+
+        let drop_rate = self.drop_rate(node_id);
+        let routed_drop_rate = self
+            .routed_drop_rates
+            .get(&node_id)
+            .cloned()
+            .unwrap_or(PRIOR_DROPPED / PRIOR_PACKETS);
+        if (drop_rate - routed_drop_rate).abs() >= REROUTE_DROP_RATE_CHANGE {
+            self.routed_drop_rates.insert(node_id, drop_rate);
+            self.version += 1;
+        }
+    }
+
+    /// Route to a node according to the routing strategy, only passing through drones
+    /// By default the most reliable route (fewest hops if equally reliable)
+    /// Every drone costs -ln(1 - drop rate), so the cost of a route is -ln(chance of delivery)
+    pub fn route_to(&self, to: NodeId) -> Option<Routing> {
+        if to == self.own_id || self.is_drone(to) {
+            return None;
+        }
+
+        // Dijkstra, per node the best (cost, hops) found so far and the node it was reached from
+        let mut best: HashMap<NodeId, (f64, usize)> = HashMap::new();
+        let mut previous: HashMap<NodeId, NodeId> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        best.insert(self.own_id, (0.0, 0));
+        queue.push(QueueEntry {
+            cost: 0.0,
+            hops: 0,
+            node_id: self.own_id,
+        });
+
+        while let Some(entry) = queue.pop() {
+            if entry.node_id == to {
+                let mut hops = vec![to];
+                let mut current = to;
+                while let Some(prev) = previous.get(&current) {
+                    hops.push(*prev);
+                    current = *prev;
+                }
+                hops.reverse();
+                return Some(Routing::with_first_hop(hops));
+            }
+
+            let outdated = best
+                .get(&entry.node_id)
+                .is_some_and(|(cost, hops)| Self::is_better(*cost, *hops, entry.cost, entry.hops));
+            // Only drones forward packets
+            if outdated || (entry.node_id != self.own_id && !self.is_drone(entry.node_id)) {
+                continue;
+            }
+
+            for neighbor_id in self.edges.get(&entry.node_id).into_iter().flatten() {
+                let cost = entry.cost + self.drop_cost(*neighbor_id);
+                let hops = entry.hops + 1;
+                let improved = match best.get(neighbor_id) {
+                    Some((best_cost, best_hops)) => {
+                        Self::is_better(cost, hops, *best_cost, *best_hops)
+                    }
+                    None => true,
+                };
+                if improved {
+                    best.insert(*neighbor_id, (cost, hops));
+                    previous.insert(*neighbor_id, entry.node_id);
+                    queue.push(QueueEntry {
+                        cost,
+                        hops,
+                        node_id: *neighbor_id,
+                    });
+                }
+            }
+        }
+
+        None
+    }
+
+    fn is_drone(&self, node_id: NodeId) -> bool {
+        matches!(self.node_types.get(&node_id), Some(NodeType::Drone))
+    }
+
+    /// Cost of sending a packet through a node, only drones can drop packets
+    fn drop_cost(&self, node_id: NodeId) -> f64 {
+        if self.strategy == RoutingStrategy::FewestHops || !self.is_drone(node_id) {
+            return 0.0;
+        }
+
+        -(1.0 - self.drop_rate(node_id).min(MAX_DROP_RATE)).ln()
+    }
+
+    /// Estimated chance that a drone drops a packet
+    fn drop_rate(&self, node_id: NodeId) -> f64 {
+        let drops = self.drops.get(&node_id).cloned().unwrap_or_default();
+        (drops.dropped as f64 + PRIOR_DROPPED)
+            / ((drops.forwarded + drops.dropped) as f64 + PRIOR_PACKETS)
+    }
+
+    /// Lower cost is better, fewer hops breaks the tie
+    fn is_better(cost: f64, hops: usize, other_cost: f64, other_hops: usize) -> bool {
+        cost.total_cmp(&other_cost).then(hops.cmp(&other_hops)) == Ordering::Less
+    }
+}
+
+/// Node to visit in the route search, the lowest cost (then fewest hops, then lowest id) comes first
+struct QueueEntry {
+    cost: f64,
+    hops: usize,
+    node_id: NodeId,
+}
+
+impl Ord for QueueEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Reversed, the BinaryHeap pops the greatest entry first
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then(other.hops.cmp(&self.hops))
+            .then(other.node_id.cmp(&self.node_id))
+    }
+}
+
+impl PartialOrd for QueueEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for QueueEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for QueueEntry {}
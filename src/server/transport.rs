@@ -0,0 +1,107 @@
+use std::{
+    io::ErrorKind,
+    net::{SocketAddr, UdpSocket},
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
+
+use crossbeam_channel::{SendError, Sender};
+use log::warn;
+use wg_2024::packet::Packet;
+
+use super::codec;
+
+/// Largest payload of a UDP datagram
+const MAX_DATAGRAM_SIZE: usize = 65507;
+
+/// Way of sending packets to a neighbor
+/// Packets from the neighbors are still received through a single channel, transports that
+/// receive from somewhere else (like a socket) forward what they receive into that channel
+pub trait PacketTransport: Send {
+    /// Send a packet to the neighbor, the packet is returned if the neighbor cannot be reached
+    fn send(&self, packet: Packet) -> Result<(), SendError<Packet>>;
+}
+
+/// Neighbors running in the same process
+impl PacketTransport for Sender<Packet> {
+    fn send(&self, packet: Packet) -> Result<(), SendError<Packet>> {
+        Sender::send(self, packet)
+    }
+}
+
+impl<T: PacketTransport + ?Sized> PacketTransport for Box<T> {
+    fn send(&self, packet: Packet) -> Result<(), SendError<Packet>> {
+        (**self).send(packet)
+    }
+}
+
+/// Neighbor running in another process, every datagram contains a single frame
+/// All neighbors can share the socket the packets are received on
+pub struct UdpTransport {
+    socket: Arc<UdpSocket>,
+    neighbor: SocketAddr,
+}
+
+impl UdpTransport {
+    pub fn new(socket: Arc<UdpSocket>, neighbor: SocketAddr) -> Self {
+        UdpTransport { socket, neighbor }
+    }
+}
+
+impl PacketTransport for UdpTransport {
+    fn send(&self, packet: Packet) -> Result<(), SendError<Packet>> {
+        let bytes = match codec::encode(&packet) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                warn!("WARNING: Could not encode packet. {}", e);
+                return Err(SendError(packet));
+            }
+        };
+
+        match self.socket.send_to(&bytes, self.neighbor) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                warn!("WARNING: Could not send packet to {}. {}", self.neighbor, e);
+                Err(SendError(packet))
+            }
+        }
+    }
+}
+
+/// Forward every packet received on the socket to the server, until the server stops receiving or the socket fails
+pub fn spawn_udp_receiver(socket: Arc<UdpSocket>, packet_send: Sender<Packet>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+        loop {
+            let (length, from) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(e) => match e.kind() {
+                    // Nothing received yet, or a neighbor was unreachable (reported by some platforms)
+                    ErrorKind::Interrupted
+                    | ErrorKind::WouldBlock
+                    | ErrorKind::TimedOut
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionRefused => continue,
+                    _ => {
+                        // Socket is broken, retrying would only spin
+                        warn!("WARNING: Stopped receiving from socket. {}", e);
+                        return;
+                    }
+                },
+            };
+
+            let packet = match codec::decode(&buffer[..length]) {
+                Ok((packet, _)) => packet,
+                Err(e) => {
+                    warn!("WARNING: Received invalid packet from {}. {}", from, e);
+                    continue;
+                }
+            };
+
+            if packet_send.send(packet).is_err() {
+                // Server is gone
+                return;
+            }
+        }
+    })
+}
@@ -0,0 +1,623 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    time::Duration,
+};
+
+use common_structs::{
+    leaf::{LeafCommand, LeafEvent},
+    message::Message,
+    types::Session,
+};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use log::{info, warn};
+use wg_2024::{
+    network::{NodeId, SourceRoutingHeader},
+    packet::{
+        Ack, FloodRequest, FloodResponse, Fragment, Nack, NackType, NodeType, Packet, PacketType,
+    },
+};
+
+use crate::server::{codec, Clock, Server, ServerConfig, ServerProtocol, VirtualClock};
+
+/// Time a client or the server takes to pass a packet on to the link
+const NODE_DELAY: Duration = Duration::from_millis(1);
+/// Smallest step the virtual clock takes when nothing is due
+const MIN_STEP: Duration = Duration::from_millis(1);
+
+/// Pseudo random numbers (splitmix64), the same seed always gives the same simulation
+pub struct SimRng(u64);
+
+impl SimRng {
+    pub fn new(seed: u64) -> Self {
+        SimRng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// True with the given probability (between 0 and 1)
+    pub fn chance(&mut self, probability: f64) -> bool {
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+}
+
+/// Behavior of a simulated drone
+#[derive(Debug, Clone)]
+pub struct DroneConfig {
+    /// Chance that a fragment is dropped
+    pub drop_rate: f64,
+    /// Time it takes for a packet sent to the drone to arrive
+    pub delay: Duration,
+}
+
+impl Default for DroneConfig {
+    fn default() -> Self {
+        DroneConfig {
+            drop_rate: 0.0,
+            delay: Duration::from_millis(1),
+        }
+    }
+}
+
+struct Drone {
+    config: DroneConfig,
+    crashed: bool,
+    dropped: u64,
+}
+
+/// Client sending requests to the server and collecting the responses
+#[derive(Default)]
+struct Client {
+    session_id: Session,
+    /// Per sender + session, the fragments received so far
+    fragments: HashMap<(NodeId, Session), BTreeMap<u64, Fragment>>,
+    /// Complete messages received, with their sender
+    messages: Vec<(NodeId, Message)>,
+}
+
+enum Node {
+    Drone(Drone),
+    Client(Client),
+    Server,
+}
+
+/// Packet travelling over a link
+struct Delivery {
+    to: NodeId,
+    from: NodeId,
+    packet: Packet,
+}
+
+/// Network of simulated drones and clients around a real server
+pub struct SimulationBuilder {
+    seed: u64,
+    drones: BTreeMap<NodeId, DroneConfig>,
+    clients: BTreeSet<NodeId>,
+    links: BTreeSet<(NodeId, NodeId)>,
+}
+
+impl SimulationBuilder {
+    pub fn new(seed: u64) -> Self {
+        SimulationBuilder {
+            seed,
+            drones: BTreeMap::new(),
+            clients: BTreeSet::new(),
+            links: BTreeSet::new(),
+        }
+    }
+
+    pub fn drone(mut self, id: NodeId, config: DroneConfig) -> Self {
+        self.drones.insert(id, config);
+        self
+    }
+
+    pub fn client(mut self, id: NodeId) -> Self {
+        self.clients.insert(id);
+        self
+    }
+
+    pub fn link(mut self, a: NodeId, b: NodeId) -> Self {
+        self.links.insert((a.min(b), a.max(b)));
+        self
+    }
+
+    /// Start the server on a virtual clock, it floods the network right away
+    pub fn build<T: ServerProtocol>(
+        self,
+        server_id: NodeId,
+        protocol: T,
+        config: ServerConfig,
+    ) -> Simulation<T> {
+        let mut nodes = BTreeMap::new();
+        for (id, config) in self.drones {
+            nodes.insert(
+                id,
+                Node::Drone(Drone {
+                    config,
+                    crashed: false,
+                    dropped: 0,
+                }),
+            );
+        }
+        for id in self.clients {
+            nodes.insert(id, Node::Client(Client::default()));
+        }
+        nodes.insert(server_id, Node::Server);
+
+        let mut links: BTreeMap<NodeId, BTreeSet<NodeId>> = BTreeMap::new();
+        for (a, b) in self.links {
+            links.entry(a).or_default().insert(b);
+            links.entry(b).or_default().insert(a);
+        }
+
+        let (controller_send, controller_events) = unbounded::<LeafEvent>();
+        let (command_send, controller_recv) = unbounded::<LeafCommand>();
+        let (server_inbox, packet_recv) = unbounded::<Packet>();
+        let mut packet_send = HashMap::new();
+        let mut server_outbox = BTreeMap::new();
+        for neighbor_id in links.get(&server_id).into_iter().flatten() {
+            let (send, recv) = unbounded::<Packet>();
+            packet_send.insert(*neighbor_id, send);
+            server_outbox.insert(*neighbor_id, recv);
+        }
+
+        let clock = VirtualClock::new();
+        let mut server = Server::create(
+            server_id,
+            controller_send,
+            controller_recv,
+            packet_recv,
+            packet_send,
+            protocol,
+            config.with_clock(Clock::Virtual(clock.clone())),
+        );
+        server.discover();
+
+        Simulation {
+            clock,
+            rng: SimRng::new(self.seed),
+            server,
+            server_id,
+            server_inbox,
+            server_outbox,
+            command_send,
+            controller_events,
+            nodes,
+            links,
+            queue: BTreeMap::new(),
+            next_delivery: 0,
+            seen_floods: HashSet::new(),
+        }
+    }
+}
+
+/// Deterministic network around a server, time only passes when the simulation runs
+pub struct Simulation<T: ServerProtocol> {
+    clock: VirtualClock,
+    rng: SimRng,
+    server: Server<T>,
+    server_id: NodeId,
+    server_inbox: Sender<Packet>,
+    /// Per neighbor of the server, the packets the server sent to it
+    server_outbox: BTreeMap<NodeId, Receiver<Packet>>,
+    command_send: Sender<LeafCommand>,
+    controller_events: Receiver<LeafEvent>,
+    nodes: BTreeMap<NodeId, Node>,
+    links: BTreeMap<NodeId, BTreeSet<NodeId>>,
+    /// Packets on their way, by arrival time and order of sending
+    queue: BTreeMap<(Duration, u64), Delivery>,
+    next_delivery: u64,
+    /// Floods the drones have already seen, by drone, initiator and flood id
+    seen_floods: HashSet<(NodeId, NodeId, u64)>,
+}
+
+impl<T: ServerProtocol> Simulation<T> {
+    pub fn server(&self) -> &Server<T> {
+        &self.server
+    }
+
+    /// Virtual time since the simulation started
+    pub fn elapsed(&self) -> Duration {
+        self.clock.elapsed()
+    }
+
+    /// Messages a client received, with their sender
+    pub fn messages(&self, client: NodeId) -> &[(NodeId, Message)] {
+        match self.nodes.get(&client) {
+            Some(Node::Client(client)) => &client.messages,
+            _ => &[],
+        }
+    }
+
+    /// Fragments a drone dropped on purpose
+    pub fn dropped(&self, drone: NodeId) -> u64 {
+        match self.nodes.get(&drone) {
+            Some(Node::Drone(drone)) => drone.dropped,
+            _ => 0,
+        }
+    }
+
+    pub fn set_drop_rate(&mut self, drone: NodeId, drop_rate: f64) {
+        if let Some(Node::Drone(drone)) = self.nodes.get_mut(&drone) {
+            drone.config.drop_rate = drop_rate;
+        }
+    }
+
+    /// Drone stops working, all its links are removed (and the server is told if it was a neighbor)
+    pub fn crash(&mut self, drone_id: NodeId) {
+        let Some(Node::Drone(drone)) = self.nodes.get_mut(&drone_id) else {
+            return;
+        };
+        drone.crashed = true;
+
+        for neighbor_id in self.links.remove(&drone_id).unwrap_or_default() {
+            if let Some(links) = self.links.get_mut(&neighbor_id) {
+                links.remove(&drone_id);
+            }
+        }
+        if self.server_outbox.remove(&drone_id).is_some()
+            && self
+                .command_send
+                .send(LeafCommand::RemoveSender(drone_id))
+                .is_err()
+        {
+            warn!(
+                "WARNING: Could not remove crashed drone {} from the server.",
+                drone_id
+            );
+        }
+    }
+
+    /// Send a request from a client to the server along the shortest working route
+    /// Returns the session of the request, None if the client cannot reach the server
+    pub fn request(&mut self, client_id: NodeId, message: Message) -> Option<Session> {
+        let route = self.route(client_id, self.server_id)?;
+        let Some(Node::Client(client)) = self.nodes.get_mut(&client_id) else {
+            return None;
+        };
+        client.session_id += 1;
+        let session_id = client.session_id;
+
+        for fragment in message.into_fragments() {
+            self.send(
+                client_id,
+                Packet::new_fragment(
+                    SourceRoutingHeader::with_first_hop(route.clone()),
+                    session_id,
+                    fragment,
+                ),
+            );
+        }
+        Some(session_id)
+    }
+
+    /// Let the network and the server run for a while
+    pub fn run_for(&mut self, duration: Duration) {
+        let end = self.clock.elapsed() + duration;
+        loop {
+            self.server.poll();
+            self.collect_server_output();
+
+            let now = self.clock.elapsed();
+            if self.deliver_due(now) {
+                // The server may have something new to handle at this same time
+                continue;
+            }
+            if now >= end {
+                return;
+            }
+
+            let mut next = end.min(now + self.server.next_timeout().max(MIN_STEP));
+            if let Some((at, _)) = self.queue.keys().next() {
+                next = next.min(*at);
+            }
+            self.clock.advance(next - now);
+        }
+    }
+
+    /// Deliver every packet that has arrived by now, returns if anything was delivered
+    fn deliver_due(&mut self, now: Duration) -> bool {
+        let mut delivered = false;
+        while let Some(entry) = self.queue.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+            let delivery = entry.remove();
+            self.deliver(delivery);
+            delivered = true;
+        }
+        delivered
+    }
+
+    /// Put everything the server sent on the links, in an order that does not depend on the server internals
+    fn collect_server_output(&mut self) {
+        let mut sent = Vec::new();
+        for (neighbor_id, recv) in self.server_outbox.iter() {
+            let mut packets: Vec<(Vec<u8>, Packet)> = recv
+                .try_iter()
+                .map(|packet| (codec::encode(&packet).unwrap_or_default(), packet))
+                .collect();
+            packets.sort_by(|a, b| a.0.cmp(&b.0));
+            sent.extend(
+                packets
+                    .into_iter()
+                    .map(|(_, packet)| (*neighbor_id, packet)),
+            );
+        }
+        for event in self.controller_events.try_iter() {
+            // Shortcuts through the simulation controller arrive at the destination directly
+            if let LeafEvent::ControllerShortcut(mut packet) = event {
+                if let Some(destination) = packet.routing_header.destination() {
+                    packet.routing_header.hop_index = packet.routing_header.hops.len() - 1;
+                    sent.push((destination, packet));
+                }
+            }
+        }
+
+        for (to, packet) in sent {
+            self.schedule(self.server_id, to, packet);
+        }
+    }
+
+    /// Send a packet to the node at the current hop of its route
+    fn send(&mut self, from: NodeId, packet: Packet) {
+        match packet.routing_header.current_hop() {
+            Some(to) if self.is_linked(from, to) => self.schedule(from, to, packet),
+            Some(to) => info!(
+                "Simulated node {} has no link to {}, packet is lost.",
+                from, to
+            ),
+            None => warn!(
+                "WARNING: Simulated node {} sent a packet without route.",
+                from
+            ),
+        }
+    }
+
+    fn schedule(&mut self, from: NodeId, to: NodeId, packet: Packet) {
+        let delay = match self.nodes.get(&to) {
+            Some(Node::Drone(drone)) => drone.config.delay,
+            _ => NODE_DELAY,
+        };
+        let at = self.clock.elapsed() + delay;
+        self.queue
+            .insert((at, self.next_delivery), Delivery { to, from, packet });
+        self.next_delivery += 1;
+    }
+
+    fn deliver(&mut self, delivery: Delivery) {
+        let Delivery { to, from, packet } = delivery;
+        match self.nodes.get(&to) {
+            Some(Node::Server) => {
+                if self.server_inbox.send(packet).is_err() {
+                    warn!("WARNING: Simulated server stopped receiving.");
+                }
+            }
+            Some(Node::Drone(drone)) if drone.crashed => {}
+            Some(Node::Drone(_)) => self.on_drone_packet(to, from, packet),
+            Some(Node::Client(_)) => self.on_client_packet(to, packet),
+            None => warn!("WARNING: Packet delivered to unknown node {}.", to),
+        }
+    }
+
+    fn on_drone_packet(&mut self, drone_id: NodeId, from: NodeId, mut packet: Packet) {
+        if let PacketType::FloodRequest(req) = packet.pack_type {
+            self.on_drone_flood(drone_id, from, packet.session_id, req);
+            return;
+        }
+
+        let routing = &packet.routing_header;
+        if routing.current_hop() != Some(drone_id) {
+            self.nack(drone_id, &packet, NackType::UnexpectedRecipient(drone_id));
+            return;
+        }
+        let Some(next_hop) = routing.hops.get(routing.hop_index + 1).cloned() else {
+            self.nack(drone_id, &packet, NackType::DestinationIsDrone);
+            return;
+        };
+
+        let is_fragment = matches!(packet.pack_type, PacketType::MsgFragment(_));
+        if !self.is_linked(drone_id, next_hop) {
+            if is_fragment {
+                self.nack(drone_id, &packet, NackType::ErrorInRouting(next_hop));
+            } else if let Some(destination) = packet.routing_header.destination() {
+                // Simulation controller shortcut
+                packet.routing_header.hop_index = packet.routing_header.hops.len() - 1;
+                self.schedule(drone_id, destination, packet);
+            }
+            return;
+        }
+
+        if is_fragment {
+            let drop_rate = match self.nodes.get(&drone_id) {
+                Some(Node::Drone(drone)) => drone.config.drop_rate,
+                _ => 0.0,
+            };
+            if self.rng.chance(drop_rate) {
+                if let Some(Node::Drone(drone)) = self.nodes.get_mut(&drone_id) {
+                    drone.dropped += 1;
+                }
+                self.nack(drone_id, &packet, NackType::Dropped);
+                return;
+            }
+        }
+
+        packet.routing_header.hop_index += 1;
+        self.schedule(drone_id, next_hop, packet);
+    }
+
+    fn on_drone_flood(
+        &mut self,
+        drone_id: NodeId,
+        from: NodeId,
+        session_id: Session,
+        mut req: FloodRequest,
+    ) {
+        req.path_trace.push((drone_id, NodeType::Drone));
+        let first_visit = self
+            .seen_floods
+            .insert((drone_id, req.initiator_id, req.flood_id));
+        let others: Vec<NodeId> = self
+            .links
+            .get(&drone_id)
+            .into_iter()
+            .flatten()
+            .filter(|neighbor_id| **neighbor_id != from)
+            .cloned()
+            .collect();
+
+        if !first_visit || others.is_empty() {
+            self.flood_response(drone_id, session_id, req);
+            return;
+        }
+        for neighbor_id in others {
+            self.schedule(
+                drone_id,
+                neighbor_id,
+                Packet::new_flood_request(
+                    SourceRoutingHeader::empty_route(),
+                    session_id,
+                    req.clone(),
+                ),
+            );
+        }
+    }
+
+    fn on_client_packet(&mut self, client_id: NodeId, packet: Packet) {
+        let Some(Node::Client(client)) = self.nodes.get_mut(&client_id) else {
+            return;
+        };
+        match packet.pack_type {
+            PacketType::MsgFragment(fragment) => {
+                let Some(source) = packet.routing_header.source() else {
+                    return;
+                };
+                let fragment_index = fragment.fragment_index;
+                let total = fragment.total_n_fragments;
+                let fragments = client
+                    .fragments
+                    .entry((source, packet.session_id))
+                    .or_default();
+                fragments.insert(fragment_index, fragment);
+                if fragments.len() as u64 == total {
+                    let fragments = client
+                        .fragments
+                        .remove(&(source, packet.session_id))
+                        .unwrap_or_default();
+                    match Message::from_fragments(fragments.into_values().collect()) {
+                        Ok(message) => client.messages.push((source, message)),
+                        Err(e) => warn!(
+                            "WARNING: Client {} got an invalid message. {}",
+                            client_id, e
+                        ),
+                    }
+                }
+
+                let mut hops = packet.routing_header.hops.clone();
+                hops.reverse();
+                self.send(
+                    client_id,
+                    Packet {
+                        routing_header: SourceRoutingHeader::with_first_hop(hops),
+                        session_id: packet.session_id,
+                        pack_type: PacketType::Ack(Ack { fragment_index }),
+                    },
+                );
+            }
+            PacketType::FloodRequest(mut req) => {
+                req.path_trace.push((client_id, NodeType::Client));
+                self.flood_response(client_id, packet.session_id, req);
+            }
+            // Clients do not resend their requests
+            _ => {}
+        }
+    }
+
+    /// Send a flood response back along the path the request took
+    fn flood_response(&mut self, from: NodeId, session_id: Session, req: FloodRequest) {
+        let mut hops: Vec<NodeId> = req.path_trace.iter().map(|(id, _)| *id).collect();
+        hops.reverse();
+        self.send(
+            from,
+            Packet::new_flood_response(
+                SourceRoutingHeader::with_first_hop(hops),
+                session_id,
+                FloodResponse {
+                    flood_id: req.flood_id,
+                    path_trace: req.path_trace,
+                },
+            ),
+        );
+    }
+
+    /// Send a nack back along the part of the route the packet travelled
+    fn nack(&mut self, drone_id: NodeId, packet: &Packet, nack_type: NackType) {
+        let PacketType::MsgFragment(fragment) = &packet.pack_type else {
+            // Only fragments are nacked
+            return;
+        };
+        let routing = &packet.routing_header;
+        let mut hops: Vec<NodeId> = routing
+            .hops
+            .iter()
+            .take(routing.hop_index + 1)
+            .cloned()
+            .collect();
+        hops.reverse();
+        if hops.first() != Some(&drone_id) {
+            hops.insert(0, drone_id);
+        }
+
+        self.send(
+            drone_id,
+            Packet::new_nack(
+                SourceRoutingHeader::with_first_hop(hops),
+                packet.session_id,
+                Nack {
+                    fragment_index: fragment.fragment_index,
+                    nack_type,
+                },
+            ),
+        );
+    }
+
+    fn is_linked(&self, a: NodeId, b: NodeId) -> bool {
+        self.links
+            .get(&a)
+            .is_some_and(|neighbors| neighbors.contains(&b))
+    }
+
+    /// Shortest route over working drones
+    fn route(&self, from: NodeId, to: NodeId) -> Option<Vec<NodeId>> {
+        let mut previous = HashMap::new();
+        let mut queue = VecDeque::from([from]);
+        while let Some(node_id) = queue.pop_front() {
+            if node_id == to {
+                let mut hops = vec![to];
+                let mut current = to;
+                while let Some(prev) = previous.get(&current) {
+                    hops.push(*prev);
+                    current = *prev;
+                }
+                hops.reverse();
+                return Some(hops);
+            }
+            // Only drones forward packets
+            if node_id != from && !matches!(self.nodes.get(&node_id), Some(Node::Drone(_))) {
+                continue;
+            }
+            for neighbor_id in self.links.get(&node_id).into_iter().flatten() {
+                if *neighbor_id != from && !previous.contains_key(neighbor_id) {
+                    previous.insert(*neighbor_id, node_id);
+                    queue.push_back(*neighbor_id);
+                }
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,203 @@
+#![cfg(test)]
+// Testing of capturing the traffic of a server and replaying it
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use common_structs::leaf::{LeafCommand, LeafEvent};
+use common_structs::message::Message;
+use crossbeam_channel::{unbounded, Sender};
+use wg_2024::network::{NodeId, SourceRoutingHeader};
+use wg_2024::packet::{Ack, Packet, PacketType};
+
+use crate::chat::ChatServer;
+use crate::server::{
+    replay, Capture, CaptureReader, CaptureRecord, Clock, Server, ServerConfig, VirtualClock,
+};
+use crate::text::{default_files, TextServer};
+
+/// Capture sink that can still be read after the server took it
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.0.lock() {
+            Ok(mut bytes) => bytes.write(buf),
+            Err(_) => Err(io::Error::other("Buffer lock poisoned")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Text server 1 answering a server type request of client 0, of which the response is acknowledged
+fn capture_request() -> Vec<u8> {
+    let (controller_send, _test_controller_recv) = unbounded::<LeafEvent>();
+    let (_test_controller_send, controller_recv) = unbounded::<LeafCommand>();
+    let (test_packet_send, packet_recv) = unbounded::<Packet>();
+    let mut packet_send = HashMap::<NodeId, Sender<Packet>>::new();
+
+    let (node0_send, node0_recv) = unbounded::<Packet>();
+    packet_send.insert(0, node0_send);
+
+    let buffer = SharedBuffer::default();
+    let capture = Capture::new(buffer.clone()).expect("Could not start capture.");
+    let mut server = Server::create(
+        1,
+        controller_send,
+        controller_recv,
+        packet_recv,
+        packet_send,
+        TextServer::new(default_files()),
+        ServerConfig::default(),
+    )
+    .with_capture(capture);
+
+    let fragments = Message::ReqServerType.into_fragments();
+    assert!(test_packet_send
+        .send(Packet::new_fragment(
+            SourceRoutingHeader::with_first_hop(vec![0, 1]),
+            777,
+            fragments[0].clone(),
+        ))
+        .is_ok());
+    server.update();
+    assert!(node0_recv.recv_timeout(Duration::from_millis(10)).is_ok()); // Ack
+    assert!(node0_recv.recv_timeout(Duration::from_millis(10)).is_ok()); // Response
+
+    assert!(test_packet_send
+        .send(Packet {
+            routing_header: SourceRoutingHeader::with_first_hop(vec![0, 1]),
+            session_id: 777,
+            pack_type: PacketType::Ack(Ack { fragment_index: 0 }),
+        })
+        .is_ok());
+    server.update();
+
+    buffer
+        .0
+        .lock()
+        .map(|bytes| bytes.clone())
+        .expect("Buffer lock poisoned.")
+}
+
+#[test]
+fn capture_records() {
+    let bytes = capture_request();
+    let entries = CaptureReader::new(bytes.as_slice())
+        .and_then(|mut reader| reader.read_all())
+        .expect("Capture could not be read.");
+
+    let records: Vec<&CaptureRecord> = entries.iter().map(|entry| &entry.record).collect();
+    assert_eq!(records.len(), 5);
+    assert!(matches!(
+        records[0],
+        CaptureRecord::Start { server: 1, neighbors, .. } if neighbors == &vec![0]
+    ));
+    assert!(matches!(records[1], CaptureRecord::Received(_)));
+    assert!(matches!(
+        records[2],
+        CaptureRecord::Sent { neighbor: 0, packet } if matches!(packet.pack_type, PacketType::Ack(_))
+    ));
+    assert!(matches!(
+        records[3],
+        CaptureRecord::Sent { neighbor: 0, packet } if matches!(packet.pack_type, PacketType::MsgFragment(_))
+    ));
+    assert!(matches!(records[4], CaptureRecord::Received(_)));
+    assert!(entries.windows(2).all(|pair| pair[0].at <= pair[1].at));
+
+    // Capture cut off in the middle of a record
+    let mut reader =
+        CaptureReader::new(&bytes[..bytes.len() - 1]).expect("Capture header could not be read.");
+    assert!((0..4).all(|_| matches!(reader.read(), Ok(Some(_)))));
+    assert!(reader.read().is_err());
+}
+
+#[test]
+fn replay_matches() {
+    let bytes = capture_request();
+    let entries = CaptureReader::new(bytes.as_slice())
+        .and_then(|mut reader| reader.read_all())
+        .expect("Capture could not be read.");
+
+    let report = replay(
+        &entries,
+        TextServer::new(default_files()),
+        ServerConfig::default(),
+    )
+    .expect("Capture could not be replayed.");
+    assert_eq!(report.expected.get(&0).map(Vec::len), Some(2));
+    assert!(report.matches());
+
+    // Another server responds differently to the same request
+    let report = replay(
+        &entries,
+        ChatServer::new(HashSet::new()),
+        ServerConfig::default(),
+    )
+    .expect("Capture could not be replayed.");
+    assert_eq!(report.mismatches(), vec![0]);
+}
+
+#[test]
+fn capture_server_clock() {
+    let (controller_send, _test_controller_recv) = unbounded::<LeafEvent>();
+    let (_test_controller_send, controller_recv) = unbounded::<LeafCommand>();
+    let (test_packet_send, packet_recv) = unbounded::<Packet>();
+
+    // Every node id is a neighbor, more than fit in a byte
+    let mut packet_send = HashMap::<NodeId, Sender<Packet>>::new();
+    let mut neighbor_recv = Vec::new();
+    for neighbor_id in 0..=NodeId::MAX {
+        let (send, recv) = unbounded::<Packet>();
+        packet_send.insert(neighbor_id, send);
+        neighbor_recv.push(recv);
+    }
+
+    let clock = VirtualClock::new();
+    let buffer = SharedBuffer::default();
+    let capture = Capture::new(buffer.clone()).expect("Could not start capture.");
+    let mut server = Server::create(
+        1,
+        controller_send,
+        controller_recv,
+        packet_recv,
+        packet_send,
+        TextServer::new(default_files()),
+        ServerConfig::default().with_clock(Clock::Virtual(clock.clone())),
+    )
+    .with_capture(capture);
+
+    // Records are timed by the clock of the server
+    clock.advance(Duration::from_secs(60));
+    let fragments = Message::ReqServerType.into_fragments();
+    assert!(test_packet_send
+        .send(Packet::new_fragment(
+            SourceRoutingHeader::with_first_hop(vec![0, 1]),
+            777,
+            fragments[0].clone(),
+        ))
+        .is_ok());
+    server.poll();
+
+    let bytes = buffer
+        .0
+        .lock()
+        .map(|bytes| bytes.clone())
+        .expect("Buffer lock poisoned.");
+    let entries = CaptureReader::new(bytes.as_slice())
+        .and_then(|mut reader| reader.read_all())
+        .expect("Capture could not be read.");
+    assert!(matches!(
+        &entries[0].record,
+        CaptureRecord::Start { neighbors, .. } if neighbors.len() == 256
+    ));
+    assert_eq!(entries[0].at, Duration::ZERO);
+    assert!(matches!(entries[1].record, CaptureRecord::Received(_)));
+    assert_eq!(entries[1].at, Duration::from_secs(60));
+}
@@ -0,0 +1,154 @@
+#![cfg(test)]
+// Testing of the wire format of packets
+
+use common_structs::message::Message;
+use wg_2024::network::SourceRoutingHeader;
+use wg_2024::packet::{
+    Ack, FloodRequest, FloodResponse, Nack, NackType, NodeType, Packet, PacketType,
+};
+
+use crate::server::codec::{
+    decode, encode, CodecError, PacketDecoder, PacketEncoder, MAX_FRAME_LENGTH, WIRE_VERSION,
+};
+
+/// A packet of every type (and every type of nack)
+fn all_packets() -> Vec<Packet> {
+    let routing = SourceRoutingHeader::with_first_hop(vec![0, 1, 2]);
+    let fragment = Message::ReqFile(String::from("helloworld")).into_fragments()[0].clone();
+
+    let mut packets = vec![
+        Packet::new_fragment(routing.clone(), 1, fragment),
+        Packet {
+            routing_header: routing.clone(),
+            session_id: 2,
+            pack_type: PacketType::Ack(Ack { fragment_index: 3 }),
+        },
+        Packet::new_flood_request(
+            SourceRoutingHeader::empty_route(),
+            4,
+            FloodRequest {
+                flood_id: 5,
+                initiator_id: 0,
+                path_trace: vec![(0, NodeType::Client), (1, NodeType::Drone)],
+            },
+        ),
+        Packet::new_flood_response(
+            routing.clone(),
+            6,
+            FloodResponse {
+                flood_id: 5,
+                path_trace: vec![
+                    (0, NodeType::Client),
+                    (1, NodeType::Drone),
+                    (2, NodeType::Server),
+                ],
+            },
+        ),
+    ];
+    for nack_type in [
+        NackType::ErrorInRouting(7),
+        NackType::DestinationIsDrone,
+        NackType::Dropped,
+        NackType::UnexpectedRecipient(8),
+    ] {
+        packets.push(Packet::new_nack(
+            routing.clone(),
+            9,
+            Nack {
+                fragment_index: 10,
+                nack_type,
+            },
+        ));
+    }
+    packets
+}
+
+fn assert_same(decoded: &Packet, packet: &Packet) {
+    assert_eq!(decoded.routing_header.hops, packet.routing_header.hops);
+    assert_eq!(
+        decoded.routing_header.hop_index,
+        packet.routing_header.hop_index
+    );
+    assert_eq!(decoded.session_id, packet.session_id);
+    assert_eq!(decoded.pack_type, packet.pack_type);
+}
+
+#[test]
+fn round_trip() {
+    for packet in all_packets() {
+        let frame = encode(&packet).expect("Packet could not be encoded.");
+        assert_eq!(frame[0], WIRE_VERSION);
+
+        let (decoded, length) = decode(&frame).expect("Frame could not be decoded.");
+        assert_eq!(length, frame.len());
+        assert_same(&decoded, &packet);
+    }
+}
+
+#[test]
+fn stream_round_trip() {
+    let packets = all_packets();
+    let mut encoder = PacketEncoder::new(Vec::new());
+    for packet in packets.iter() {
+        assert!(encoder.write(packet).is_ok());
+    }
+
+    let bytes = encoder.into_inner();
+    let mut decoder = PacketDecoder::new(bytes.as_slice());
+    for packet in packets.iter() {
+        match decoder.read() {
+            Ok(Some(decoded)) => assert_same(&decoded, packet),
+            Ok(None) => panic!("Stream ended early."),
+            Err(e) => panic!("Frame could not be decoded: {}", e),
+        }
+    }
+    assert!(matches!(decoder.read(), Ok(None)));
+}
+
+#[test]
+fn truncated() {
+    let frame = encode(&all_packets()[0]).expect("Packet could not be encoded.");
+
+    for length in [1, 4, frame.len() - 1] {
+        assert!(matches!(
+            decode(&frame[..length]),
+            Err(CodecError::Truncated { available, .. }) if available == length
+        ));
+        assert!(matches!(
+            PacketDecoder::new(&frame[..length]).read(),
+            Err(CodecError::Truncated { available, .. }) if available == length
+        ));
+    }
+}
+
+#[test]
+fn oversized() {
+    let mut frame = vec![WIRE_VERSION];
+    frame.extend_from_slice(&(MAX_FRAME_LENGTH as u32 + 1).to_be_bytes());
+    frame.extend_from_slice(&[0; 16]);
+
+    assert!(matches!(
+        decode(&frame),
+        Err(CodecError::Oversized(length)) if length == MAX_FRAME_LENGTH + 1
+    ));
+    assert!(matches!(
+        PacketDecoder::new(frame.as_slice()).read(),
+        Err(CodecError::Oversized(_))
+    ));
+}
+
+#[test]
+fn invalid() {
+    let mut frame = encode(&all_packets()[1]).expect("Packet could not be encoded.");
+    frame[0] = WIRE_VERSION + 1;
+    assert!(matches!(
+        decode(&frame),
+        Err(CodecError::UnsupportedVersion(version)) if version == WIRE_VERSION + 1
+    ));
+
+    // Correct header, but no packet
+    let mut frame = vec![WIRE_VERSION];
+    frame.extend_from_slice(&4u32.to_be_bytes());
+    frame.extend_from_slice(&[0xff; 4]);
+    assert!(matches!(decode(&frame), Err(CodecError::Invalid(_))));
+}
@@ -0,0 +1,235 @@
+#![cfg(test)]
+// Testing of the reassembly of arbitrary fragments by the server
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use common_structs::leaf::{LeafCommand, LeafEvent};
+use common_structs::message::Message;
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use proptest::prelude::*;
+use wg_2024::network::{NodeId, SourceRoutingHeader};
+use wg_2024::packet::{Fragment, Packet, FRAGMENT_DSIZE};
+
+use crate::server::{Server, ServerConfig, ServerProtocol, ServerSenders};
+
+const CLIENT: NodeId = 0;
+const SERVER: NodeId = 1;
+
+type Received = Arc<Mutex<Vec<(NodeId, Message)>>>;
+
+/// Remember every message that is received
+struct RecordingServer {
+    messages: Received,
+}
+
+impl ServerProtocol for RecordingServer {
+    fn on_message(
+        &mut self,
+        _server: NodeId,
+        _senders: &mut ServerSenders,
+        from: NodeId,
+        message: Message,
+        _session_id: u64,
+    ) {
+        if let Ok(mut messages) = self.messages.lock() {
+            messages.push((from, message));
+        }
+    }
+}
+
+/// Server with a few neighbors, the channels are kept open for as long as the server lives
+struct Harness {
+    server: Server<RecordingServer>,
+    packet_send: Sender<Packet>,
+    messages: Received,
+    _controller: (Sender<LeafCommand>, Receiver<LeafEvent>),
+    _neighbors: Vec<Receiver<Packet>>,
+}
+
+impl Harness {
+    fn new() -> Self {
+        let (controller_send, controller_events) = unbounded::<LeafEvent>();
+        let (command_send, controller_recv) = unbounded::<LeafCommand>();
+        let (packet_send, packet_recv) = unbounded::<Packet>();
+
+        let mut neighbors = Vec::new();
+        let mut neighbor_send = HashMap::<NodeId, Sender<Packet>>::new();
+        for neighbor_id in [CLIENT, 2, 3] {
+            let (send, recv) = unbounded::<Packet>();
+            neighbor_send.insert(neighbor_id, send);
+            neighbors.push(recv);
+        }
+
+        let messages = Received::default();
+        let server = Server::create(
+            SERVER,
+            controller_send,
+            controller_recv,
+            packet_recv,
+            neighbor_send,
+            RecordingServer {
+                messages: messages.clone(),
+            },
+            ServerConfig::default(),
+        );
+
+        Harness {
+            server,
+            packet_send,
+            messages,
+            _controller: (command_send, controller_events),
+            _neighbors: neighbors,
+        }
+    }
+
+    fn receive(&mut self, packets: Vec<Packet>) {
+        for packet in packets {
+            self.packet_send
+                .send(packet)
+                .expect("Server stopped receiving");
+            self.server.poll();
+        }
+    }
+
+    fn messages(&self) -> Vec<(NodeId, Message)> {
+        self.messages
+            .lock()
+            .map(|messages| messages.clone())
+            .expect("Messages lock is poisoned")
+    }
+}
+
+fn from_client(session_id: u64, fragment: Fragment) -> Packet {
+    Packet::new_fragment(
+        SourceRoutingHeader::with_first_hop(vec![CLIENT, SERVER]),
+        session_id,
+        fragment,
+    )
+}
+
+fn arb_message() -> impl Strategy<Value = Message> {
+    (any::<NodeId>(), prop::collection::vec(any::<u8>(), 0..2000))
+        .prop_map(|(to, chat_msg)| Message::ReqChatSend { to, chat_msg })
+}
+
+/// Fragments of a message in random order, some of them more than once
+fn arb_delivery() -> impl Strategy<Value = (Message, Vec<Fragment>)> {
+    arb_message()
+        .prop_flat_map(|message| {
+            let fragments = message.clone().into_fragments();
+            let count = fragments.len();
+            (
+                Just(message),
+                Just(fragments).prop_shuffle(),
+                prop::collection::vec((0..count, 0..count), 0..count),
+            )
+        })
+        .prop_map(|(message, mut fragments, duplicates)| {
+            for (index, position) in duplicates {
+                let duplicate = fragments[index].clone();
+                fragments.insert(position, duplicate);
+            }
+            (message, fragments)
+        })
+}
+
+/// Fragments of a message in random order, with at least one of them missing
+fn arb_incomplete() -> impl Strategy<Value = Vec<Fragment>> {
+    arb_message()
+        .prop_flat_map(|message| {
+            let fragments = message.into_fragments();
+            let count = fragments.len();
+            (Just(fragments).prop_shuffle(), 1..=count)
+        })
+        .prop_map(|(mut fragments, missing)| {
+            fragments.truncate(fragments.len() - missing);
+            fragments
+        })
+}
+
+/// Mostly small numbers, so fragments of the same message meet, and sometimes anything
+fn arb_count() -> impl Strategy<Value = u64> {
+    prop_oneof![3 => 0..8u64, 1 => any::<u64>()]
+}
+
+fn arb_fragment() -> impl Strategy<Value = Fragment> {
+    (
+        arb_count(),
+        arb_count(),
+        any::<u8>(),
+        prop::collection::vec(any::<u8>(), FRAGMENT_DSIZE),
+    )
+        .prop_map(|(fragment_index, total_n_fragments, length, bytes)| {
+            let mut data = [0; FRAGMENT_DSIZE];
+            data.copy_from_slice(&bytes);
+            Fragment {
+                fragment_index,
+                total_n_fragments,
+                length,
+                data,
+            }
+        })
+}
+
+/// Any route, the hop index mostly points at one of the hops
+fn arb_routing() -> impl Strategy<Value = SourceRoutingHeader> {
+    prop::collection::vec(0..5 as NodeId, 0..6)
+        .prop_flat_map(|hops| {
+            let hop_count = hops.len().max(1);
+            (
+                Just(hops),
+                prop_oneof![3 => 0..hop_count, 1 => any::<usize>()],
+            )
+        })
+        .prop_map(|(hops, hop_index)| SourceRoutingHeader { hop_index, hops })
+}
+
+fn arb_packet() -> impl Strategy<Value = Packet> {
+    (arb_routing(), 0..4u64, arb_fragment()).prop_map(|(routing, session_id, fragment)| {
+        Packet::new_fragment(routing, session_id, fragment)
+    })
+}
+
+proptest! {
+    #[test]
+    fn reassemble_any_order((message, fragments) in arb_delivery()) {
+        let mut harness = Harness::new();
+        harness.receive(fragments.into_iter().map(|f| from_client(7, f)).collect());
+
+        prop_assert_eq!(harness.messages(), vec![(CLIENT, message)]);
+        prop_assert_eq!(harness.server.stats().reassembly_failures, 0);
+    }
+
+    #[test]
+    fn incomplete_not_delivered(fragments in arb_incomplete()) {
+        let mut harness = Harness::new();
+        harness.receive(fragments.into_iter().map(|f| from_client(7, f)).collect());
+
+        prop_assert!(harness.messages().is_empty());
+    }
+
+    #[test]
+    fn arbitrary_fragments(packets in prop::collection::vec(arb_packet(), 0..64)) {
+        // Must not panic, whatever arrives
+        let mut harness = Harness::new();
+        harness.receive(packets);
+    }
+
+    #[test]
+    fn valid_after_arbitrary(
+        packets in prop::collection::vec(arb_packet(), 0..32),
+        (message, fragments) in arb_delivery(),
+    ) {
+        let mut harness = Harness::new();
+        harness.receive(packets);
+        let before = harness.messages().len();
+
+        // Session that none of the arbitrary fragments use
+        harness.receive(fragments.into_iter().map(|f| from_client(7, f)).collect());
+
+        let messages = harness.messages();
+        prop_assert_eq!(messages.len(), before + 1);
+        prop_assert_eq!(&messages[before], &(CLIENT, message));
+    }
+}
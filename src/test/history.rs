@@ -0,0 +1,183 @@
+#![cfg(test)]
+// Testing of the history of sent packets
+
+use std::time::{Duration, Instant};
+
+use common_structs::message::Message;
+use wg_2024::{
+    network::{NodeId, SourceRoutingHeader},
+    packet::Packet,
+};
+
+use crate::server::{HistoryEvictions, HistoryLimits, PacketHistory, RetransmitPolicy};
+
+fn fragment_packets(session_id: u64) -> Vec<Packet> {
+    fragment_packets_on(vec![0, 0], session_id)
+}
+
+fn fragment_packets_on(hops: Vec<NodeId>, session_id: u64) -> Vec<Packet> {
+    Message::ReqChatSend {
+        to: 0,
+        chat_msg: vec![42; 1000],
+    }
+    .into_fragments()
+    .into_iter()
+    .map(|fragment| {
+        Packet::new_fragment(
+            SourceRoutingHeader::with_first_hop(hops.clone()),
+            session_id,
+            fragment,
+        )
+    })
+    .collect()
+}
+
+#[test]
+fn ack_completes_session() {
+    let mut history = PacketHistory::new(HistoryLimits::default(), RetransmitPolicy::default());
+    let packets = fragment_packets(1);
+    let fragment_count = packets.len() as u64;
+    let now = Instant::now();
+    for packet in packets {
+        history.insert(packet, now);
+    }
+
+    for i in 0..fragment_count {
+        assert!(history.ack(&(0, 1, i)));
+    }
+    assert!(!history.ack(&(0, 1, 0)));
+
+    assert_eq!(
+        *history.evictions(),
+        HistoryEvictions {
+            acked: fragment_count,
+            completed_sessions: 1,
+            given_up: 0,
+            over_capacity: 0,
+        }
+    );
+    assert!(history.get(&(0, 1, 0)).is_none());
+}
+
+#[test]
+fn max_entries() {
+    let mut history = PacketHistory::new(
+        HistoryLimits {
+            max_entries: 2,
+            max_bytes: usize::MAX,
+        },
+        RetransmitPolicy::default(),
+    );
+    let now = Instant::now();
+    for packet in fragment_packets(1).into_iter().take(3) {
+        history.insert(packet, now);
+    }
+
+    // Least recently used packet is evicted
+    assert!(history.get(&(0, 1, 0)).is_none());
+    assert!(history.get(&(0, 1, 1)).is_some());
+    assert!(history.get(&(0, 1, 2)).is_some());
+    assert_eq!(history.evictions().over_capacity, 1);
+}
+
+#[test]
+fn max_bytes() {
+    let mut history = PacketHistory::new(
+        HistoryLimits {
+            max_entries: usize::MAX,
+            max_bytes: 256,
+        },
+        RetransmitPolicy::default(),
+    );
+    let now = Instant::now();
+    let mut packets = fragment_packets(1);
+    let resent = packets[0].clone();
+    for packet in packets.drain(..2) {
+        history.insert(packet, now);
+    }
+
+    // Resending a packet makes it the most recently used
+    history.insert(resent, now);
+    history.insert(packets.remove(0), now);
+
+    assert!(history.get(&(0, 1, 0)).is_some());
+    assert!(history.get(&(0, 1, 1)).is_none());
+    assert!(history.get(&(0, 1, 2)).is_some());
+    assert_eq!(history.evictions().over_capacity, 1);
+}
+
+#[test]
+fn timed_out() {
+    let policy = RetransmitPolicy {
+        ack_timeout: Duration::from_millis(100),
+        backoff: 2,
+        max_retries: 5,
+    };
+    let mut history = PacketHistory::new(HistoryLimits::default(), policy);
+    let now = Instant::now();
+    for packet in fragment_packets(1).into_iter().take(2) {
+        history.insert(packet, now);
+    }
+    history.record_retry(&(0, 1, 1));
+
+    let mut timed_out = history.timed_out(now + Duration::from_millis(150));
+    timed_out.sort();
+    assert_eq!(timed_out, vec![(0, 1, 0)]);
+    assert_eq!(
+        history.next_timeout(now + Duration::from_millis(150)),
+        Some(Duration::ZERO)
+    );
+
+    let mut timed_out = history.timed_out(now + Duration::from_millis(200));
+    timed_out.sort();
+    assert_eq!(timed_out, vec![(0, 1, 0), (0, 1, 1)]);
+}
+
+#[test]
+fn parked() {
+    let policy = RetransmitPolicy {
+        ack_timeout: Duration::from_millis(100),
+        backoff: 2,
+        max_retries: 5,
+    };
+    let mut history = PacketHistory::new(HistoryLimits::default(), policy);
+    let now = Instant::now();
+    let packet = fragment_packets(1).remove(0);
+    history.insert(packet.clone(), now);
+
+    // A packet waiting for a route does not time out, not even after another failed attempt
+    history.park(&(0, 1, 0));
+    history.record_retry(&(0, 1, 0));
+    assert!(history.timed_out(now + Duration::from_secs(10)).is_empty());
+    assert_eq!(history.next_timeout(now), None);
+
+    // Resending the packet starts the timeout again
+    let later = now + Duration::from_secs(10);
+    history.insert(packet, later);
+    assert_eq!(history.get(&(0, 1, 0)).map(|sent| sent.retries), Some(1));
+    assert_eq!(
+        history.next_timeout(later),
+        Some(Duration::from_millis(200))
+    );
+}
+
+#[test]
+fn same_session_other_destination() {
+    let mut history = PacketHistory::new(HistoryLimits::default(), RetransmitPolicy::default());
+    let now = Instant::now();
+    // Two destinations that happen to use the same session id
+    for hops in [vec![0, 1, 2], vec![0, 3, 4]] {
+        history.insert(fragment_packets_on(hops, 7).remove(0), now);
+    }
+    assert!(history.has_session(2, 7));
+    assert!(history.has_session(4, 7));
+    assert!(!history.has_session(1, 7));
+
+    // The nack is matched to the packet by the route it came back on
+    assert_eq!(history.find_nacked(7, 0, &[3, 0]), Some((4, 7, 0)));
+    assert_eq!(history.find_nacked(7, 0, &[2, 1, 0]), Some((2, 7, 0)));
+
+    assert!(history.ack(&(2, 7, 0)));
+    assert!(history.get(&(4, 7, 0)).is_some());
+    assert_eq!(history.find_nacked(7, 0, &[1, 0]), Some((4, 7, 0)));
+}
@@ -12,10 +12,19 @@ use wg_2024::{
 
 use crate::server::{ServerProtocol, ServerSenders};
 
+mod capture;
 mod chat;
+mod codec;
+mod fragments;
+mod history;
 mod media;
+mod pool;
+mod reassembly;
 mod server;
+mod sim;
 mod text;
+mod topology;
+mod transport;
 
 pub fn setup_node0() -> (ServerSenders, Receiver<Packet>) {
     let (controller_send, _test_controller_recv) = unbounded::<LeafEvent>();
@@ -28,7 +37,7 @@ pub fn setup_node0() -> (ServerSenders, Receiver<Packet>) {
     node_path.insert(0, SourceRoutingHeader::with_first_hop(vec![0, 0]));
 
     (
-        ServerSenders::with_node_path(controller_send, packet_send, node_path),
+        ServerSenders::with_node_path(0, controller_send, packet_send, node_path),
         node0_recv,
     )
 }
@@ -0,0 +1,237 @@
+#![cfg(test)]
+// Testing of request handling on a worker pool
+
+use std::collections::HashMap;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::server::{
+    ConcurrentProtocol, DrainSummary, Reply, Server, ServerConfig, ServerProtocol, WorkerPool,
+};
+use crate::test::panic_to_message;
+use common_structs::leaf::{LeafCommand, LeafEvent};
+use common_structs::message::Message;
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+use wg_2024::network::{NodeId, SourceRoutingHeader};
+use wg_2024::packet::{Ack, Packet, PacketType};
+
+/// Send back any messages that we receive, taking a while for messages containing "slow"
+/// and panicking for messages containing "panic"
+struct SlowEchoServer {}
+
+impl ConcurrentProtocol for SlowEchoServer {
+    fn handle(
+        &self,
+        _server: NodeId,
+        from: NodeId,
+        message: Message,
+        session_id: u64,
+    ) -> Vec<Reply> {
+        if let Message::ReqChatSend { chat_msg, .. } = &message {
+            if chat_msg.as_slice() == b"slow" {
+                thread::sleep(Duration::from_millis(200));
+            }
+            if chat_msg.as_slice() == b"panic" {
+                panic!("Request could not be handled");
+            }
+        }
+        vec![Reply::response(from, message, session_id)]
+    }
+}
+
+#[test]
+fn slow_request_does_not_block() {
+    let (controller_send, _test_controller_recv) = unbounded::<LeafEvent>();
+    let (_test_controller_send, controller_recv) = unbounded::<LeafCommand>();
+    let (test_packet_send, packet_recv) = unbounded::<Packet>();
+    let mut packet_send = HashMap::<NodeId, Sender<Packet>>::new();
+
+    let (node0_send, node0_recv) = unbounded::<Packet>();
+    packet_send.insert(0, node0_send);
+
+    let mut server = Server::create(
+        1,
+        controller_send,
+        controller_recv,
+        packet_recv,
+        packet_send,
+        WorkerPool::new(SlowEchoServer {}, 2),
+        ServerConfig::default(),
+    );
+
+    let slow = Message::ReqChatSend {
+        to: 0,
+        chat_msg: b"slow".to_vec(),
+    };
+    let fast = Message::ReqChatSend {
+        to: 0,
+        chat_msg: b"fast".to_vec(),
+    };
+    for (session_id, message) in [(1, slow.clone()), (2, fast.clone())] {
+        let fragments = message.into_fragments();
+        assert_eq!(fragments.len(), 1);
+        assert!(test_packet_send
+            .send(Packet::new_fragment(
+                SourceRoutingHeader::with_first_hop(vec![0, 1]),
+                session_id,
+                fragments[0].clone(),
+            ))
+            .is_ok());
+    }
+
+    // Both requests are acked while the slow one is still being handled
+    for session_id in [1, 2] {
+        server.update();
+        match node0_recv.recv_timeout(Duration::from_millis(10)) {
+            Ok(p) => {
+                assert_eq!(p.session_id, session_id);
+                assert_eq!(p.pack_type, PacketType::Ack(Ack { fragment_index: 0 }));
+            }
+            Err(e) => panic!("Did not receive packet (expected ACK): {}", e),
+        }
+    }
+
+    // The fast request is answered before the slow one
+    let packet = update_until_reply(&mut server, &node0_recv);
+    assert!(packet.as_ref().is_ok_and(|p| p.session_id == 2));
+    assert_eq!(panic_to_message(packet), fast);
+
+    let packet = update_until_reply(&mut server, &node0_recv);
+    assert!(packet.as_ref().is_ok_and(|p| p.session_id == 1));
+    assert_eq!(panic_to_message(packet), slow);
+}
+
+/// Keep the server going until it sends a packet, an update can return on a tick before any reply is ready
+fn update_until_reply<T: ServerProtocol>(
+    server: &mut Server<T>,
+    node_recv: &Receiver<Packet>,
+) -> Result<Packet, RecvTimeoutError> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Instant::now() < deadline {
+        server.update();
+        if let Ok(packet) = node_recv.try_recv() {
+            return Ok(packet);
+        }
+    }
+    Err(RecvTimeoutError::Timeout)
+}
+
+#[test]
+fn kill_waits_for_requests() {
+    let (controller_send, _test_controller_recv) = unbounded::<LeafEvent>();
+    let (test_controller_send, controller_recv) = unbounded::<LeafCommand>();
+    let (test_packet_send, packet_recv) = unbounded::<Packet>();
+    let mut packet_send = HashMap::<NodeId, Sender<Packet>>::new();
+
+    let (node0_send, node0_recv) = unbounded::<Packet>();
+    packet_send.insert(0, node0_send);
+
+    let mut server = Server::create(
+        1,
+        controller_send,
+        controller_recv,
+        packet_recv,
+        packet_send,
+        WorkerPool::new(SlowEchoServer {}, 1),
+        ServerConfig::default(),
+    );
+
+    let slow = Message::ReqChatSend {
+        to: 0,
+        chat_msg: b"slow".to_vec(),
+    };
+    assert!(test_packet_send
+        .send(Packet::new_fragment(
+            SourceRoutingHeader::with_first_hop(vec![0, 1]),
+            1,
+            slow.clone().into_fragments()[0].clone(),
+        ))
+        .is_ok());
+    server.update();
+    assert!(node0_recv.recv_timeout(Duration::from_millis(10)).is_ok()); // Ack
+
+    // Request is still being handled, so the server keeps going
+    assert!(test_controller_send.send(LeafCommand::Kill).is_ok());
+    server.update();
+    assert!(server.drain_summary().is_none());
+
+    let mut reply = None;
+    while reply.is_none() {
+        server.update();
+        assert!(server.drain_summary().is_none());
+        reply = node0_recv.try_recv().ok();
+    }
+    assert!(reply.as_ref().is_some_and(|p| p.session_id == 1));
+    assert_eq!(panic_to_message(reply.ok_or("No reply")), slow);
+
+    // Server stops once the reply is acknowledged
+    assert!(test_packet_send
+        .send(Packet {
+            routing_header: SourceRoutingHeader::with_first_hop(vec![0, 1]),
+            session_id: 1,
+            pack_type: PacketType::Ack(Ack { fragment_index: 0 }),
+        })
+        .is_ok());
+    server.update();
+    assert_eq!(
+        server.drain_summary(),
+        Some(&DrainSummary {
+            unacked_fragments: 0,
+            queued_messages: 0,
+            incomplete_messages: 0,
+            pending_requests: 0,
+            timed_out: false,
+        })
+    );
+}
+
+#[test]
+fn kill_after_panicking_request() {
+    let (controller_send, _test_controller_recv) = unbounded::<LeafEvent>();
+    let (test_controller_send, controller_recv) = unbounded::<LeafCommand>();
+    let (test_packet_send, packet_recv) = unbounded::<Packet>();
+    let mut packet_send = HashMap::<NodeId, Sender<Packet>>::new();
+
+    let (node0_send, node0_recv) = unbounded::<Packet>();
+    packet_send.insert(0, node0_send);
+
+    let mut server = Server::create(
+        1,
+        controller_send,
+        controller_recv,
+        packet_recv,
+        packet_send,
+        WorkerPool::new(SlowEchoServer {}, 1),
+        ServerConfig::default().with_drain_timeout(Duration::from_secs(5)),
+    );
+
+    let panic = Message::ReqChatSend {
+        to: 0,
+        chat_msg: b"panic".to_vec(),
+    };
+    assert!(test_packet_send
+        .send(Packet::new_fragment(
+            SourceRoutingHeader::with_first_hop(vec![0, 1]),
+            1,
+            panic.into_fragments()[0].clone(),
+        ))
+        .is_ok());
+    server.update();
+    assert!(node0_recv.recv_timeout(Duration::from_millis(10)).is_ok()); // Ack
+
+    // The request that panicked is not waited for until the drain timeout
+    assert!(test_controller_send.send(LeafCommand::Kill).is_ok());
+    while server.drain_summary().is_none() {
+        server.update();
+    }
+    assert_eq!(
+        server.drain_summary(),
+        Some(&DrainSummary {
+            unacked_fragments: 0,
+            queued_messages: 0,
+            incomplete_messages: 0,
+            pending_requests: 0,
+            timed_out: false,
+        })
+    );
+}
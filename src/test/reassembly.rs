@@ -0,0 +1,307 @@
+#![cfg(test)]
+// Testing of the reassembly of fragments into messages
+
+use std::time::{Duration, Instant};
+
+use common_structs::message::Message;
+use wg_2024::packet::{Fragment, FRAGMENT_DSIZE};
+
+use crate::server::{FragmentOutcome, Reassembly, ReassemblyBuffer, ReassemblyLimits};
+
+fn message_fragments() -> (Message, Vec<Fragment>) {
+    let message = Message::ReqChatSend {
+        to: 0,
+        chat_msg: vec![42; 1000],
+    };
+    let fragments = message.clone().into_fragments();
+    (message, fragments)
+}
+
+fn assert_complete(outcome: FragmentOutcome, message: &Message) {
+    match outcome {
+        FragmentOutcome::Complete(buffer) => match Message::from_fragments(buffer.into_fragments())
+        {
+            Ok(reassembled) => assert_eq!(reassembled, *message),
+            Err(e) => panic!("Fragments are not a message: {}", e),
+        },
+        _ => panic!("Message should be complete."),
+    }
+}
+
+#[test]
+fn out_of_order() {
+    let (message, fragments) = message_fragments();
+    let mut reassembly = Reassembly::new(ReassemblyLimits::default());
+    let now = Instant::now();
+
+    let (last, rest) = fragments.split_last().expect("Message has fragments");
+    for fragment in rest.iter().rev() {
+        assert!(matches!(
+            reassembly.add_fragment(1, 5, fragment.clone(), now),
+            FragmentOutcome::Incomplete
+        ));
+    }
+    assert_complete(reassembly.add_fragment(1, 5, last.clone(), now), &message);
+}
+
+#[test]
+fn duplicate() {
+    let (message, fragments) = message_fragments();
+    let mut reassembly = Reassembly::new(ReassemblyLimits::default());
+    let now = Instant::now();
+
+    assert!(matches!(
+        reassembly.add_fragment(1, 5, fragments[0].clone(), now),
+        FragmentOutcome::Incomplete
+    ));
+    assert!(matches!(
+        reassembly.add_fragment(1, 5, fragments[0].clone(), now),
+        FragmentOutcome::Duplicate
+    ));
+
+    let mut outcome = FragmentOutcome::Incomplete;
+    for fragment in fragments.iter().skip(1) {
+        outcome = reassembly.add_fragment(1, 5, fragment.clone(), now);
+    }
+    assert_complete(outcome, &message);
+
+    // Retransmission of a message that was already processed
+    assert!(matches!(
+        reassembly.add_fragment(1, 5, fragments[1].clone(), now),
+        FragmentOutcome::AlreadyCompleted
+    ));
+}
+
+#[test]
+fn sessions_per_sender() {
+    let (message, fragments) = message_fragments();
+    let mut reassembly = Reassembly::new(ReassemblyLimits::default());
+    let now = Instant::now();
+
+    // Same session id from different senders are different messages
+    for from in [5, 6] {
+        let mut outcome = FragmentOutcome::Incomplete;
+        for fragment in fragments.iter() {
+            outcome = reassembly.add_fragment(1, from, fragment.clone(), now);
+        }
+        assert_complete(outcome, &message);
+    }
+}
+
+#[test]
+fn invalid_index() {
+    let (_, fragments) = message_fragments();
+    let mut reassembly = Reassembly::new(ReassemblyLimits::default());
+    let now = Instant::now();
+
+    let mut fragment = fragments[0].clone();
+    fragment.fragment_index = fragment.total_n_fragments;
+    assert!(matches!(
+        reassembly.add_fragment(1, 5, fragment, now),
+        FragmentOutcome::Invalid(_)
+    ));
+
+    let mut fragment = fragments[1].clone();
+    assert!(matches!(
+        reassembly.add_fragment(1, 5, fragments[0].clone(), now),
+        FragmentOutcome::Incomplete
+    ));
+    fragment.total_n_fragments += 1;
+    assert!(matches!(
+        reassembly.add_fragment(1, 5, fragment, now),
+        FragmentOutcome::Invalid(_)
+    ));
+}
+
+#[test]
+fn expire() {
+    let (message, fragments) = message_fragments();
+    let mut reassembly = Reassembly::new(ReassemblyLimits {
+        timeout: Duration::from_secs(1),
+        ..Default::default()
+    });
+    let now = Instant::now();
+
+    reassembly.add_fragment(1, 5, fragments[0].clone(), now);
+    reassembly.add_fragment(2, 5, fragments[0].clone(), now);
+    reassembly.add_fragment(2, 5, fragments[1].clone(), now + Duration::from_millis(500));
+
+    assert_eq!(
+        reassembly.expire(now + Duration::from_secs(1)),
+        vec![(1, 5)]
+    );
+
+    // Expired message has to be sent again from the start
+    let later = now + Duration::from_secs(1);
+    let mut outcome = FragmentOutcome::Incomplete;
+    for fragment in fragments.iter().skip(1) {
+        outcome = reassembly.add_fragment(1, 5, fragment.clone(), later);
+    }
+    assert!(matches!(outcome, FragmentOutcome::Incomplete));
+    assert_complete(
+        reassembly.add_fragment(1, 5, fragments[0].clone(), later),
+        &message,
+    );
+}
+
+#[test]
+fn max_sessions_per_node() {
+    let (_, fragments) = message_fragments();
+    let mut reassembly = Reassembly::new(ReassemblyLimits {
+        max_sessions_per_node: 2,
+        ..Default::default()
+    });
+    let now = Instant::now();
+
+    for session_id in 0..2 {
+        assert!(matches!(
+            reassembly.add_fragment(session_id, 5, fragments[0].clone(), now),
+            FragmentOutcome::Incomplete
+        ));
+    }
+    assert!(matches!(
+        reassembly.add_fragment(2, 5, fragments[0].clone(), now),
+        FragmentOutcome::Rejected(_)
+    ));
+
+    // The message is dropped once, even when a slot becomes available
+    reassembly.expire(now + Duration::from_secs(60));
+    assert!(matches!(
+        reassembly.add_fragment(2, 5, fragments[1].clone(), now),
+        FragmentOutcome::AlreadyRejected
+    ));
+
+    // Other senders are not affected
+    assert!(matches!(
+        reassembly.add_fragment(2, 6, fragments[0].clone(), now),
+        FragmentOutcome::Incomplete
+    ));
+}
+
+#[test]
+fn max_fragments() {
+    let (_, fragments) = message_fragments();
+    let mut reassembly = Reassembly::new(ReassemblyLimits {
+        max_fragments_per_message: 4,
+        max_total_fragments: 5,
+        ..Default::default()
+    });
+    let now = Instant::now();
+
+    let mut huge = fragments[0].clone();
+    huge.total_n_fragments = u64::MAX;
+    assert!(matches!(
+        reassembly.add_fragment(1, 5, huge, now),
+        FragmentOutcome::Rejected(_)
+    ));
+
+    // Only the fragments received count towards the total, not the size that is announced
+    let mut small = fragments[0].clone();
+    small.total_n_fragments = 4;
+    for session_id in 2..7 {
+        assert!(matches!(
+            reassembly.add_fragment(
+                session_id,
+                5,
+                small.clone(),
+                now + Duration::from_millis(session_id)
+            ),
+            FragmentOutcome::Incomplete
+        ));
+    }
+
+    // The message that waited longest for a fragment makes room
+    let later = now + Duration::from_secs(1);
+    assert!(matches!(
+        reassembly.add_fragment(7, 6, small.clone(), later),
+        FragmentOutcome::Incomplete
+    ));
+    assert_eq!(reassembly.take_evicted(), vec![(2, 5)]);
+    assert!(reassembly.take_evicted().is_empty());
+    assert!(!reassembly.is_known(2, 5));
+    small.fragment_index = 1;
+    assert!(matches!(
+        reassembly.add_fragment(2, 5, small.clone(), later),
+        FragmentOutcome::AlreadyRejected
+    ));
+    assert!(matches!(
+        reassembly.add_fragment(3, 5, small, later),
+        FragmentOutcome::Incomplete
+    ));
+    assert_eq!(reassembly.take_evicted(), vec![(4, 5)]);
+}
+
+#[test]
+fn max_total_fragments_single_message() {
+    let (_, fragments) = message_fragments();
+    let mut reassembly = Reassembly::new(ReassemblyLimits {
+        max_total_fragments: 2,
+        ..Default::default()
+    });
+    let now = Instant::now();
+
+    // A message larger than the total on its own cannot make room by dropping others
+    let mut outcome = FragmentOutcome::Incomplete;
+    for fragment in fragments.iter().take(3) {
+        outcome = reassembly.add_fragment(1, 5, fragment.clone(), now);
+    }
+    assert!(matches!(outcome, FragmentOutcome::Rejected(_)));
+    assert!(reassembly.take_evicted().is_empty());
+    assert!(!reassembly.is_known(1, 5));
+}
+
+#[test]
+fn buffer() {
+    let (message, fragments) = message_fragments();
+    let mut buffer = ReassemblyBuffer::new(fragments.len() as u64).expect("Buffer fits in memory");
+
+    for fragment in fragments.iter().rev() {
+        assert!(!buffer.is_complete());
+        assert!(buffer.insert(fragment));
+        assert!(buffer.contains(fragment.fragment_index));
+    }
+    assert!(!buffer.insert(&fragments[0]));
+    assert!(buffer.is_complete());
+
+    let expected_data: Vec<u8> = fragments
+        .iter()
+        .flat_map(|fragment| fragment.data[..fragment.length as usize].to_vec())
+        .collect();
+    let fragment_count = fragments.len() as u64;
+    let mut rebuilt = ReassemblyBuffer::new(fragment_count).expect("Buffer fits in memory");
+    for fragment in fragments.iter() {
+        rebuilt.insert(fragment);
+    }
+    assert_eq!(rebuilt.into_bytes(), expected_data);
+
+    let rebuilt_fragments = buffer.into_fragments();
+    assert_eq!(rebuilt_fragments, fragments);
+    match Message::from_fragments(rebuilt_fragments) {
+        Ok(reassembled) => assert_eq!(reassembled, message),
+        Err(e) => panic!("Fragments are not a message: {}", e),
+    }
+}
+
+#[test]
+fn buffer_out_of_range() {
+    let (_, fragments) = message_fragments();
+    let mut buffer = ReassemblyBuffer::new(2).expect("Buffer fits in memory");
+    assert!(!buffer.insert(&fragments[2]));
+    assert!(!buffer.contains(2));
+    assert!(ReassemblyBuffer::new(u64::MAX).is_none());
+}
+
+#[test]
+fn buffer_grows_lazily() {
+    let (_, fragments) = message_fragments();
+    let mut buffer = ReassemblyBuffer::new(65536).expect("Buffer fits in memory");
+    let empty = buffer.allocated_bytes();
+    assert!(empty < 65536 * FRAGMENT_DSIZE / 64);
+
+    // A fragment at the end only allocates its own block
+    let mut last = fragments[0].clone();
+    last.fragment_index = 65535;
+    last.total_n_fragments = 65536;
+    assert!(buffer.insert(&last));
+    assert!(buffer.allocated_bytes() < empty + 65536 * FRAGMENT_DSIZE / 64);
+}
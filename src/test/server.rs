@@ -2,16 +2,24 @@
 // Testing of the protocol-independent server implementation
 
 use std::collections::HashMap;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use crate::server::{Server, ServerProtocol};
-use crate::test::panic_to_message_multi;
+use crate::server::{
+    Clock, ConfigError, DrainSummary, OutboundLimits, PacketTransport, ReassemblyLimits,
+    RetransmitPolicy, Server, ServerConfig, ServerError, ServerProtocol, ServerReport,
+    ShortcutPolicy, VirtualClock,
+};
+use crate::test::{panic_to_message, panic_to_message_multi};
 use common_structs::leaf::{LeafCommand, LeafEvent};
 use common_structs::message::Message;
 use common_structs::types::Routing;
-use crossbeam_channel::{unbounded, Sender};
+use crossbeam_channel::{unbounded, SendError, Sender};
 use wg_2024::network::{NodeId, SourceRoutingHeader};
-use wg_2024::packet::{Ack, FloodRequest, Nack, NackType, NodeType, Packet, PacketType};
+use wg_2024::packet::{
+    Ack, FloodRequest, FloodResponse, Fragment, Nack, NackType, NodeType, Packet, PacketType,
+    FRAGMENT_DSIZE,
+};
 
 struct EchoServer {}
 
@@ -31,7 +39,9 @@ impl ServerProtocol for EchoServer {
         message: Message,
         session_id: u64,
     ) {
-        Server::<EchoServer>::send_message(server, senders, from, message, Some(session_id));
+        // Errors are part of the expected behavior of some tests
+        let _ =
+            Server::<EchoServer>::send_message(server, senders, from, message, Some(session_id));
     }
 }
 
@@ -46,12 +56,13 @@ fn fragments() {
     packet_send.insert(0, node0_send);
 
     let mut server = Server::create(
-        0,
+        1,
         controller_send,
         controller_recv,
         packet_recv,
         packet_send,
         EchoServer::new(),
+        ServerConfig::default(),
     );
 
     let message = Message::ReqChatSend {
@@ -73,7 +84,7 @@ Etiam varius tortor vitae tincidunt rutrum. In tortor mauris, imperdiet malesuad
     let session_id = 777;
     for fragment in fragments {
         let res = test_packet_send.send(Packet::new_fragment(
-            SourceRoutingHeader::with_first_hop(vec![0, 0]),
+            SourceRoutingHeader::with_first_hop(vec![0, 1]),
             session_id,
             fragment,
         ));
@@ -117,12 +128,13 @@ fn flood_request() {
     packet_send.insert(0, node0_send);
 
     let mut server = Server::create(
-        0,
+        1,
         controller_send,
         controller_recv,
         packet_recv,
         packet_send,
         EchoServer::new(),
+        ServerConfig::default(),
     );
 
     let flood_id = 123;
@@ -148,7 +160,7 @@ fn flood_request() {
                 assert_eq!(resp.flood_id, flood_id);
                 assert_eq!(
                     resp.path_trace,
-                    [path_trace, vec![(0, NodeType::Server)]].concat()
+                    [path_trace, vec![(1, NodeType::Server)]].concat()
                 )
             }
             _ => panic!("Packet is not a flood response."),
@@ -168,12 +180,13 @@ fn nack() {
     packet_send.insert(0, node0_send);
 
     let mut server = Server::create(
-        0,
+        1,
         controller_send,
         controller_recv,
         packet_recv,
         packet_send,
         EchoServer::new(),
+        ServerConfig::default(),
     );
 
     let message = Message::ReqChatSend {
@@ -195,7 +208,7 @@ Etiam varius tortor vitae tincidunt rutrum. In tortor mauris, imperdiet malesuad
     let session_id = 777;
     for fragment in fragments.clone() {
         let res = test_packet_send.send(Packet::new_fragment(
-            SourceRoutingHeader::with_first_hop(vec![0, 0]),
+            SourceRoutingHeader::with_first_hop(vec![0, 1]),
             session_id,
             fragment,
         ));
@@ -255,3 +268,1391 @@ Etiam varius tortor vitae tincidunt rutrum. In tortor mauris, imperdiet malesuad
         Err(e) => panic!("No packet could be received: {}", e),
     }
 }
+
+#[test]
+fn retransmit() {
+    let (controller_send, _test_controller_recv) = unbounded::<LeafEvent>();
+    let (_test_controller_send, controller_recv) = unbounded::<LeafCommand>();
+    let (test_packet_send, packet_recv) = unbounded::<Packet>();
+    let mut packet_send = HashMap::<NodeId, Sender<Packet>>::new();
+
+    let (node0_send, node0_recv) = unbounded::<Packet>();
+    packet_send.insert(0, node0_send);
+
+    let mut server = Server::create(
+        1,
+        controller_send,
+        controller_recv,
+        packet_recv,
+        packet_send,
+        EchoServer::new(),
+        ServerConfig::default().with_retransmit_policy(RetransmitPolicy {
+            ack_timeout: Duration::from_millis(20),
+            backoff: 2,
+            max_retries: 1,
+        }),
+    );
+
+    let message = Message::ReqServerType;
+    let fragments = message.clone().into_fragments();
+    assert_eq!(fragments.len(), 1);
+    let session_id = 777;
+    assert!(test_packet_send
+        .send(Packet::new_fragment(
+            SourceRoutingHeader::with_first_hop(vec![0, 1]),
+            session_id,
+            fragments[0].clone(),
+        ))
+        .is_ok());
+
+    server.update();
+
+    // Ack of the request, followed by the echoed response
+    match node0_recv.recv_timeout(Duration::from_millis(10)) {
+        Ok(p) => assert_eq!(p.pack_type, PacketType::Ack(Ack { fragment_index: 0 })),
+        Err(e) => panic!("Did not receive packet (expected ACK): {}", e),
+    }
+    let response = match node0_recv.recv_timeout(Duration::from_millis(10)) {
+        Ok(p) => p,
+        Err(e) => panic!("Did not receive packet (expected response): {}", e),
+    };
+
+    // No ack is sent back, so the response is resent once the timeout expires
+    server.update();
+    match node0_recv.recv_timeout(Duration::from_millis(10)) {
+        Ok(p) => {
+            assert_eq!(p.session_id, response.session_id);
+            assert_eq!(p.pack_type, response.pack_type);
+        }
+        Err(e) => panic!("Did not receive packet (expected resend): {}", e),
+    }
+
+    // Maximum amount of retries is reached, the response is not resent again
+    server.update();
+    server.update();
+    assert!(node0_recv.try_recv().is_err());
+}
+
+#[test]
+fn dropped_gives_up() {
+    let (mut server, _test_controller_send, test_packet_send, node0_recv) =
+        setup_unacked_response(ServerConfig::default());
+
+    // Drone drops every packet, the response is resent until the maximum amount of retries
+    let mut resends = 0;
+    for _ in 0..10 {
+        assert!(test_packet_send
+            .send(Packet::new_nack(
+                SourceRoutingHeader::with_first_hop(vec![0, 1]),
+                777,
+                Nack {
+                    fragment_index: 0,
+                    nack_type: NackType::Dropped,
+                },
+            ))
+            .is_ok());
+        server.poll();
+        resends += node0_recv
+            .try_iter()
+            .filter(|p| matches!(p.pack_type, PacketType::MsgFragment(_)))
+            .count();
+    }
+    assert_eq!(resends, 5);
+    assert_eq!(server.history_evictions().given_up, 1);
+}
+
+#[test]
+fn ack_stops_retransmit() {
+    let (controller_send, _test_controller_recv) = unbounded::<LeafEvent>();
+    let (_test_controller_send, controller_recv) = unbounded::<LeafCommand>();
+    let (test_packet_send, packet_recv) = unbounded::<Packet>();
+    let mut packet_send = HashMap::<NodeId, Sender<Packet>>::new();
+
+    let (node0_send, node0_recv) = unbounded::<Packet>();
+    packet_send.insert(0, node0_send);
+
+    let mut server = Server::create(
+        1,
+        controller_send,
+        controller_recv,
+        packet_recv,
+        packet_send,
+        EchoServer::new(),
+        ServerConfig::default().with_retransmit_policy(RetransmitPolicy {
+            ack_timeout: Duration::from_millis(20),
+            backoff: 2,
+            max_retries: 5,
+        }),
+    );
+
+    let fragments = Message::ReqServerType.into_fragments();
+    let session_id = 777;
+    assert!(test_packet_send
+        .send(Packet::new_fragment(
+            SourceRoutingHeader::with_first_hop(vec![0, 1]),
+            session_id,
+            fragments[0].clone(),
+        ))
+        .is_ok());
+
+    server.update();
+    assert!(node0_recv.recv_timeout(Duration::from_millis(10)).is_ok()); // Ack
+    assert!(node0_recv.recv_timeout(Duration::from_millis(10)).is_ok()); // Response
+
+    assert!(test_packet_send
+        .send(Packet {
+            routing_header: SourceRoutingHeader::with_first_hop(vec![0, 1]),
+            session_id,
+            pack_type: PacketType::Ack(Ack { fragment_index: 0 }),
+        })
+        .is_ok());
+
+    server.update(); // Process ack
+    server.update(); // Timeout expires
+    assert!(node0_recv.try_recv().is_err());
+}
+
+/// Server 0 with drone neighbors 1 and 2, which knows client 5 through both drones (most recently through drone 1)
+fn setup_two_routes() -> (
+    Server<EchoServer>,
+    Sender<LeafCommand>,
+    Sender<Packet>,
+    crossbeam_channel::Receiver<Packet>,
+    crossbeam_channel::Receiver<Packet>,
+) {
+    let (controller_send, _test_controller_recv) = unbounded::<LeafEvent>();
+    let (test_controller_send, controller_recv) = unbounded::<LeafCommand>();
+    let (test_packet_send, packet_recv) = unbounded::<Packet>();
+    let mut packet_send = HashMap::<NodeId, Sender<Packet>>::new();
+
+    let (node1_send, node1_recv) = unbounded::<Packet>();
+    packet_send.insert(1, node1_send);
+    let (node2_send, node2_recv) = unbounded::<Packet>();
+    packet_send.insert(2, node2_send);
+
+    let mut server = Server::create(
+        0,
+        controller_send,
+        controller_recv,
+        packet_recv,
+        packet_send,
+        EchoServer::new(),
+        ServerConfig::default(),
+    );
+
+    // Client 5 floods through drone 2
+    assert!(test_packet_send
+        .send(Packet::new_flood_request(
+            Routing::empty_route(),
+            1,
+            FloodRequest {
+                flood_id: 1,
+                initiator_id: 5,
+                path_trace: vec![(5, NodeType::Client), (2, NodeType::Drone)],
+            },
+        ))
+        .is_ok());
+    server.update();
+    assert!(node2_recv.recv_timeout(Duration::from_millis(10)).is_ok()); // Flood response
+
+    // Client 5 sends a request through drone 1
+    let fragments = Message::ReqServerType.into_fragments();
+    assert!(test_packet_send
+        .send(Packet::new_fragment(
+            SourceRoutingHeader::with_first_hop(vec![5, 1, 0]),
+            777,
+            fragments[0].clone(),
+        ))
+        .is_ok());
+    server.update();
+    assert!(node1_recv.recv_timeout(Duration::from_millis(10)).is_ok()); // Ack
+    assert!(node1_recv.recv_timeout(Duration::from_millis(10)).is_ok()); // Response
+
+    (
+        server,
+        test_controller_send,
+        test_packet_send,
+        node1_recv,
+        node2_recv,
+    )
+}
+
+#[test]
+fn reroute_alternative() {
+    let (mut server, _test_controller_send, test_packet_send, node1_recv, node2_recv) =
+        setup_two_routes();
+
+    // Drone 1 cannot reach client 5
+    assert!(test_packet_send
+        .send(Packet::new_nack(
+            SourceRoutingHeader::with_first_hop(vec![1, 0]),
+            777,
+            Nack {
+                fragment_index: 0,
+                nack_type: NackType::ErrorInRouting(5),
+            },
+        ))
+        .is_ok());
+    server.update();
+
+    // Response is resent through drone 2
+    match node2_recv.recv_timeout(Duration::from_millis(10)) {
+        Ok(packet) => {
+            assert_eq!(packet.session_id, 777);
+            assert_eq!(packet.routing_header.hops, vec![0, 2, 5]);
+            assert!(matches!(packet.pack_type, PacketType::MsgFragment(_)));
+        }
+        Err(e) => panic!("Did not receive packet (expected resend): {}", e),
+    }
+    assert!(node1_recv.try_recv().is_err());
+}
+
+#[test]
+fn reroute_unexpected_recipient() {
+    let (controller_send, _test_controller_recv) = unbounded::<LeafEvent>();
+    let (_test_controller_send, controller_recv) = unbounded::<LeafCommand>();
+    let (test_packet_send, packet_recv) = unbounded::<Packet>();
+    let mut packet_send = HashMap::<NodeId, Sender<Packet>>::new();
+
+    let (node1_send, node1_recv) = unbounded::<Packet>();
+    packet_send.insert(1, node1_send);
+
+    let mut server = Server::create(
+        0,
+        controller_send,
+        controller_recv,
+        packet_recv,
+        packet_send,
+        EchoServer::new(),
+        ServerConfig::default(),
+    );
+
+    // Client 5 floods through drones 4, 3 and 1
+    assert!(test_packet_send
+        .send(Packet::new_flood_request(
+            Routing::empty_route(),
+            1,
+            FloodRequest {
+                flood_id: 1,
+                initiator_id: 5,
+                path_trace: vec![
+                    (5, NodeType::Client),
+                    (4, NodeType::Drone),
+                    (3, NodeType::Drone),
+                    (1, NodeType::Drone),
+                ],
+            },
+        ))
+        .is_ok());
+    server.update();
+    assert!(node1_recv.recv_timeout(Duration::from_millis(10)).is_ok()); // Flood response
+
+    // Client 5 sends a request through drones 3 and 1
+    let fragments = Message::ReqServerType.into_fragments();
+    assert!(test_packet_send
+        .send(Packet::new_fragment(
+            SourceRoutingHeader::with_first_hop(vec![5, 3, 1, 0]),
+            777,
+            fragments[0].clone(),
+        ))
+        .is_ok());
+    server.update();
+    assert!(node1_recv.recv_timeout(Duration::from_millis(10)).is_ok()); // Ack
+    match node1_recv.recv_timeout(Duration::from_millis(10)) {
+        Ok(packet) => assert_eq!(packet.routing_header.hops, vec![0, 1, 3, 5]),
+        Err(e) => panic!("Did not receive packet (expected response): {}", e),
+    }
+
+    // Drone 3 forwarded the response to client 5, which was not expecting it
+    assert!(test_packet_send
+        .send(Packet::new_nack(
+            SourceRoutingHeader::with_first_hop(vec![5, 3, 1, 0]),
+            777,
+            Nack {
+                fragment_index: 0,
+                nack_type: NackType::UnexpectedRecipient(5),
+            },
+        ))
+        .is_ok());
+    server.update();
+
+    // Only the link into client 5 is removed, the link from drone 1 to drone 3 is still used
+    match node1_recv.recv_timeout(Duration::from_millis(10)) {
+        Ok(packet) => {
+            assert_eq!(packet.session_id, 777);
+            assert_eq!(packet.routing_header.hops, vec![0, 1, 3, 4, 5]);
+            assert!(matches!(packet.pack_type, PacketType::MsgFragment(_)));
+        }
+        Err(e) => panic!("Did not receive packet (expected resend): {}", e),
+    }
+}
+
+#[test]
+fn reroute_flood() {
+    let (mut server, _test_controller_send, test_packet_send, node1_recv, node2_recv) =
+        setup_two_routes();
+
+    // Drone 1 cannot reach client 5, response is resent through drone 2
+    assert!(test_packet_send
+        .send(Packet::new_nack(
+            SourceRoutingHeader::with_first_hop(vec![1, 0]),
+            777,
+            Nack {
+                fragment_index: 0,
+                nack_type: NackType::ErrorInRouting(5),
+            },
+        ))
+        .is_ok());
+    server.update();
+    match node2_recv.recv_timeout(Duration::from_millis(10)) {
+        Ok(packet) => assert_eq!(packet.routing_header.hops, vec![0, 2, 5]),
+        Err(e) => panic!("Did not receive packet (expected resend): {}", e),
+    }
+
+    // Drone 2 cannot reach client 5 either
+    assert!(test_packet_send
+        .send(Packet::new_nack(
+            SourceRoutingHeader::with_first_hop(vec![2, 0]),
+            777,
+            Nack {
+                fragment_index: 0,
+                nack_type: NackType::ErrorInRouting(5),
+            },
+        ))
+        .is_ok());
+    server.update();
+
+    // No route is known anymore, a flood is started through all neighbors
+    let mut flood_id = 0;
+    for node_recv in [&node1_recv, &node2_recv] {
+        match node_recv.recv_timeout(Duration::from_millis(10)) {
+            Ok(packet) => match packet.pack_type {
+                PacketType::FloodRequest(req) => {
+                    assert_eq!(req.initiator_id, 0);
+                    assert_eq!(req.path_trace, vec![(0, NodeType::Server)]);
+                    flood_id = req.flood_id;
+                }
+                _ => panic!("Packet is not a flood request."),
+            },
+            Err(e) => panic!("Did not receive packet (expected flood request): {}", e),
+        }
+    }
+
+    // Flood response finds a new route through drone 1 and drone 3
+    assert!(test_packet_send
+        .send(Packet::new_flood_response(
+            SourceRoutingHeader::with_first_hop(vec![5, 3, 1, 0]),
+            1,
+            FloodResponse {
+                flood_id,
+                path_trace: vec![
+                    (0, NodeType::Server),
+                    (1, NodeType::Drone),
+                    (3, NodeType::Drone),
+                    (5, NodeType::Client),
+                ],
+            },
+        ))
+        .is_ok());
+    server.update();
+
+    match node1_recv.recv_timeout(Duration::from_millis(10)) {
+        Ok(packet) => {
+            assert_eq!(packet.session_id, 777);
+            assert_eq!(packet.routing_header.hops, vec![0, 1, 3, 5]);
+            assert!(matches!(packet.pack_type, PacketType::MsgFragment(_)));
+        }
+        Err(e) => panic!("Did not receive packet (expected resend): {}", e),
+    }
+}
+
+#[test]
+fn duplicate_fragments() {
+    let (controller_send, _test_controller_recv) = unbounded::<LeafEvent>();
+    let (_test_controller_send, controller_recv) = unbounded::<LeafCommand>();
+    let (test_packet_send, packet_recv) = unbounded::<Packet>();
+    let mut packet_send = HashMap::<NodeId, Sender<Packet>>::new();
+
+    let (node0_send, node0_recv) = unbounded::<Packet>();
+    packet_send.insert(0, node0_send);
+
+    let mut server = Server::create(
+        1,
+        controller_send,
+        controller_recv,
+        packet_recv,
+        packet_send,
+        EchoServer::new(),
+        ServerConfig::default(),
+    );
+
+    let message = Message::ReqChatSend {
+        to: 0,
+        chat_msg: vec![42; 300],
+    };
+    let fragments = message.clone().into_fragments();
+    assert!(fragments.len() > 1);
+    let session_id = 777;
+
+    // First fragment twice, then the rest, then a retransmission of the last fragment
+    let mut sent_fragments = vec![fragments[0].clone()];
+    sent_fragments.extend(fragments.iter().cloned());
+    sent_fragments.push(fragments[fragments.len() - 1].clone());
+    for fragment in sent_fragments.iter() {
+        assert!(test_packet_send
+            .send(Packet::new_fragment(
+                SourceRoutingHeader::with_first_hop(vec![0, 1]),
+                session_id,
+                fragment.clone(),
+            ))
+            .is_ok());
+    }
+
+    let mut acks = 0;
+    let mut received_packets = Vec::new();
+    for _ in 0..sent_fragments.len() {
+        server.update();
+    }
+    while let Ok(packet) = node0_recv.try_recv() {
+        match packet.pack_type {
+            PacketType::Ack(_) => acks += 1,
+            PacketType::MsgFragment(_) => received_packets.push(Ok::<Packet, String>(packet)),
+            _ => panic!("Unexpected packet type."),
+        }
+    }
+
+    // Every fragment is acked, but the message is only answered once
+    assert_eq!(acks, sent_fragments.len());
+    assert_eq!(received_packets.len(), fragments.len());
+    assert_eq!(panic_to_message_multi(received_packets), message);
+}
+
+/// Count the ticks that happened
+struct TickServer {
+    ticks: Sender<Instant>,
+}
+
+impl ServerProtocol for TickServer {
+    fn on_message(
+        &mut self,
+        _server: NodeId,
+        _senders: &mut crate::server::ServerSenders,
+        _from: NodeId,
+        _message: Message,
+        _session_id: u64,
+    ) {
+    }
+
+    fn on_tick(
+        &mut self,
+        _server: NodeId,
+        _senders: &mut crate::server::ServerSenders,
+        now: Instant,
+    ) {
+        assert!(self.ticks.send(now).is_ok());
+    }
+}
+
+#[test]
+fn tick() {
+    let (controller_send, _test_controller_recv) = unbounded::<LeafEvent>();
+    let (_test_controller_send, controller_recv) = unbounded::<LeafCommand>();
+    let (_test_packet_send, packet_recv) = unbounded::<Packet>();
+    let (ticks_send, ticks_recv) = unbounded::<Instant>();
+
+    let clock = VirtualClock::new();
+    let mut server = Server::create(
+        1,
+        controller_send,
+        controller_recv,
+        packet_recv,
+        HashMap::new(),
+        TickServer { ticks: ticks_send },
+        ServerConfig::default()
+            .with_tick_interval(Duration::from_millis(5))
+            .with_clock(Clock::Virtual(clock.clone())),
+    );
+
+    // Without any packets arriving, the server still ticks once every interval
+    for _ in 0..3 {
+        server.poll();
+        server.poll();
+        clock.advance(Duration::from_millis(5));
+    }
+    let ticks: Vec<Instant> = ticks_recv.try_iter().collect();
+    assert_eq!(ticks.len(), 3);
+    assert!(ticks
+        .windows(2)
+        .all(|t| t[1] - t[0] == Duration::from_millis(5)));
+}
+
+/// Server that answered a request, of which the response is not acknowledged yet
+fn setup_unacked_response(
+    config: ServerConfig,
+) -> (
+    Server<EchoServer>,
+    Sender<LeafCommand>,
+    Sender<Packet>,
+    crossbeam_channel::Receiver<Packet>,
+) {
+    let (controller_send, _test_controller_recv) = unbounded::<LeafEvent>();
+    let (test_controller_send, controller_recv) = unbounded::<LeafCommand>();
+    let (test_packet_send, packet_recv) = unbounded::<Packet>();
+    let mut packet_send = HashMap::<NodeId, Sender<Packet>>::new();
+
+    let (node0_send, node0_recv) = unbounded::<Packet>();
+    packet_send.insert(0, node0_send);
+
+    let mut server = Server::create(
+        1,
+        controller_send,
+        controller_recv,
+        packet_recv,
+        packet_send,
+        EchoServer::new(),
+        config.with_retransmit_policy(RetransmitPolicy {
+            ack_timeout: Duration::from_secs(1),
+            backoff: 2,
+            max_retries: 5,
+        }),
+    );
+
+    let fragments = Message::ReqServerType.into_fragments();
+    assert!(test_packet_send
+        .send(Packet::new_fragment(
+            SourceRoutingHeader::with_first_hop(vec![0, 1]),
+            777,
+            fragments[0].clone(),
+        ))
+        .is_ok());
+
+    server.update();
+    assert!(node0_recv.recv_timeout(Duration::from_millis(10)).is_ok()); // Ack
+    assert!(node0_recv.recv_timeout(Duration::from_millis(10)).is_ok()); // Response
+
+    (server, test_controller_send, test_packet_send, node0_recv)
+}
+
+#[test]
+fn kill_drains() {
+    let (mut server, test_controller_send, test_packet_send, node0_recv) =
+        setup_unacked_response(ServerConfig::default().with_drain_timeout(Duration::from_secs(5)));
+
+    // Response is not acknowledged yet, so the server keeps going
+    assert!(test_controller_send.send(LeafCommand::Kill).is_ok());
+    server.update();
+    assert!(server.drain_summary().is_none());
+
+    // New requests are not accepted anymore, the sender is told they did not arrive
+    let fragments = Message::ReqServerType.into_fragments();
+    assert!(test_packet_send
+        .send(Packet::new_fragment(
+            SourceRoutingHeader::with_first_hop(vec![0, 1]),
+            778,
+            fragments[0].clone(),
+        ))
+        .is_ok());
+    server.update();
+    match node0_recv.try_recv() {
+        Ok(p) => {
+            assert_eq!(p.session_id, 778);
+            assert_eq!(
+                p.pack_type,
+                PacketType::Nack(Nack {
+                    fragment_index: 0,
+                    nack_type: NackType::Dropped,
+                })
+            );
+        }
+        Err(e) => panic!("Did not receive packet (expected NACK): {}", e),
+    }
+    assert!(node0_recv.try_recv().is_err());
+    assert!(server.drain_summary().is_none());
+
+    // Server stops once the response is acknowledged
+    assert!(test_packet_send
+        .send(Packet {
+            routing_header: SourceRoutingHeader::with_first_hop(vec![0, 1]),
+            session_id: 777,
+            pack_type: PacketType::Ack(Ack { fragment_index: 0 }),
+        })
+        .is_ok());
+    server.update();
+    assert_eq!(
+        server.drain_summary(),
+        Some(&DrainSummary {
+            unacked_fragments: 0,
+            queued_messages: 0,
+            incomplete_messages: 0,
+            pending_requests: 0,
+            timed_out: false,
+        })
+    );
+}
+
+#[test]
+fn kill_drain_timeout() {
+    let (mut server, test_controller_send, _test_packet_send, _node0_recv) = setup_unacked_response(
+        ServerConfig::default().with_drain_timeout(Duration::from_millis(10)),
+    );
+
+    assert!(test_controller_send.send(LeafCommand::Kill).is_ok());
+    server.update();
+    assert!(server.drain_summary().is_none());
+
+    // Ack never arrives, the server stops once the deadline expires
+    server.update();
+    assert_eq!(
+        server.drain_summary(),
+        Some(&DrainSummary {
+            unacked_fragments: 1,
+            queued_messages: 0,
+            incomplete_messages: 0,
+            pending_requests: 0,
+            timed_out: true,
+        })
+    );
+}
+
+#[test]
+fn kill_drain_incomplete_message() {
+    let (mut server, test_controller_send, test_packet_send, _node0_recv) = setup_unacked_response(
+        ServerConfig::default().with_drain_timeout(Duration::from_millis(10)),
+    );
+    let (report_send, report_recv) = unbounded::<ServerReport>();
+    server = server.with_report_sender(report_send);
+
+    // First fragment of a message, the rest never arrives
+    let message = Message::RespMedia(vec![42; 3 * FRAGMENT_DSIZE]);
+    let fragments = message.into_fragments();
+    assert!(test_packet_send
+        .send(Packet::new_fragment(
+            SourceRoutingHeader::with_first_hop(vec![0, 1]),
+            778,
+            fragments[0].clone(),
+        ))
+        .is_ok());
+    server.update();
+
+    assert!(test_controller_send.send(LeafCommand::Kill).is_ok());
+    server.update();
+    assert!(server.drain_summary().is_none());
+
+    std::thread::sleep(Duration::from_millis(20));
+    server.update();
+    let expected = DrainSummary {
+        unacked_fragments: 1,
+        queued_messages: 0,
+        incomplete_messages: 1,
+        pending_requests: 0,
+        timed_out: true,
+    };
+    assert_eq!(server.drain_summary(), Some(&expected));
+    assert!(report_recv
+        .try_iter()
+        .any(|report| report == ServerReport::Drained(expected.clone())));
+}
+
+#[test]
+fn controller_hang_up() {
+    let (mut server, test_controller_send, test_packet_send, node0_recv) =
+        setup_unacked_response(ServerConfig::default());
+
+    // A closed controller channel does not keep the server from handling packets
+    drop(test_controller_send);
+    let fragments = Message::ReqServerType.into_fragments();
+    assert!(test_packet_send
+        .send(Packet::new_fragment(
+            SourceRoutingHeader::with_first_hop(vec![0, 1]),
+            778,
+            fragments[0].clone(),
+        ))
+        .is_ok());
+    server.update();
+    server.update();
+    match node0_recv.try_recv() {
+        Ok(p) => {
+            assert_eq!(p.session_id, 778);
+            assert_eq!(p.pack_type, PacketType::Ack(Ack { fragment_index: 0 }));
+        }
+        Err(e) => panic!("Did not receive packet (expected ACK): {}", e),
+    }
+}
+
+#[test]
+fn stats() {
+    let (mut server, _test_controller_send, test_packet_send, node0_recv) = setup_unacked_response(
+        ServerConfig::default().with_tick_interval(Duration::from_millis(5)),
+    );
+    let (report_send, report_recv) = unbounded::<ServerReport>();
+    server = server.with_report_sender(report_send);
+
+    assert_eq!(
+        server.stats().messages_received.get("ReqServerType"),
+        Some(&1)
+    );
+    assert_eq!(server.stats().fragments_sent, 1);
+    assert!(server.stats().bytes_served > 0);
+
+    // Dropped response is resent
+    assert!(test_packet_send
+        .send(Packet::new_nack(
+            SourceRoutingHeader::with_first_hop(vec![0, 1]),
+            777,
+            Nack {
+                fragment_index: 0,
+                nack_type: NackType::Dropped,
+            },
+        ))
+        .is_ok());
+    server.update();
+    assert!(node0_recv.recv_timeout(Duration::from_millis(10)).is_ok());
+    assert_eq!(server.stats().nacks_received.dropped, 1);
+    assert_eq!(server.stats().resends, 1);
+    assert_eq!(server.stats().fragments_sent, 2);
+
+    assert!(test_packet_send
+        .send(Packet {
+            routing_header: SourceRoutingHeader::with_first_hop(vec![0, 1]),
+            session_id: 777,
+            pack_type: PacketType::Ack(Ack { fragment_index: 0 }),
+        })
+        .is_ok());
+    server.update();
+    assert_eq!(server.stats().acks_received, 1);
+
+    // Snapshot is sent on the next tick
+    server.update();
+    let snapshot = report_recv.try_iter().last();
+    assert_eq!(snapshot, Some(ServerReport::Stats(server.stats().clone())));
+}
+
+#[test]
+fn dropped_message_reported() {
+    let (controller_send, _test_controller_recv) = unbounded::<LeafEvent>();
+    let (_test_controller_send, controller_recv) = unbounded::<LeafCommand>();
+    let (test_packet_send, packet_recv) = unbounded::<Packet>();
+    let (report_send, report_recv) = unbounded::<ServerReport>();
+    let mut packet_send = HashMap::<NodeId, Sender<Packet>>::new();
+    let (node0_send, _node0_recv) = unbounded::<Packet>();
+    packet_send.insert(0, node0_send);
+
+    let mut server = Server::create(
+        1,
+        controller_send,
+        controller_recv,
+        packet_recv,
+        packet_send,
+        EchoServer::new(),
+        ServerConfig::default().with_reassembly_limits(ReassemblyLimits {
+            max_fragments_per_message: 4,
+            ..ReassemblyLimits::default()
+        }),
+    )
+    .with_report_sender(report_send);
+
+    // Message of more fragments than allowed is dropped right away, and reported only once
+    let mut fragment = Message::ReqServerType.into_fragments()[0].clone();
+    fragment.total_n_fragments = 5;
+    for fragment_index in 0..2 {
+        fragment.fragment_index = fragment_index;
+        assert!(test_packet_send
+            .send(Packet::new_fragment(
+                SourceRoutingHeader::with_first_hop(vec![0, 1]),
+                777,
+                fragment.clone(),
+            ))
+            .is_ok());
+    }
+    server.poll();
+
+    let reports: Vec<ServerReport> = report_recv
+        .try_iter()
+        .filter(|report| !matches!(report, ServerReport::Stats(_)))
+        .collect();
+    match reports.as_slice() {
+        [ServerReport::MessageDropped {
+            from, session_id, ..
+        }] => {
+            assert_eq!(*from, 0);
+            assert_eq!(*session_id, 777);
+        }
+        reports => panic!("Dropped message was not reported once: {:?}", reports),
+    }
+}
+
+#[test]
+fn shortcut_policy() {
+    for acks in [true, false] {
+        let (controller_send, test_controller_recv) = unbounded::<LeafEvent>();
+        let (_test_controller_send, controller_recv) = unbounded::<LeafCommand>();
+        let (test_packet_send, packet_recv) = unbounded::<Packet>();
+        let mut packet_send = HashMap::<NodeId, Sender<Packet>>::new();
+
+        // Neighbor cannot be reached
+        let (node0_send, node0_recv) = unbounded::<Packet>();
+        packet_send.insert(0, node0_send);
+        drop(node0_recv);
+
+        let mut server = Server::create(
+            1,
+            controller_send,
+            controller_recv,
+            packet_recv,
+            packet_send,
+            EchoServer::new(),
+            ServerConfig::default().with_shortcut_policy(ShortcutPolicy {
+                acks,
+                nacks: true,
+                flood_responses: true,
+            }),
+        );
+
+        let fragments = Message::ReqServerType.into_fragments();
+        assert!(test_packet_send
+            .send(Packet::new_fragment(
+                SourceRoutingHeader::with_first_hop(vec![0, 1]),
+                777,
+                fragments[0].clone(),
+            ))
+            .is_ok());
+        server.update();
+
+        let shortcut_acks = test_controller_recv
+            .try_iter()
+            .filter(|event| {
+                matches!(event, LeafEvent::ControllerShortcut(packet) if matches!(packet.pack_type, PacketType::Ack(_)))
+            })
+            .count();
+        assert_eq!(shortcut_acks, if acks { 1 } else { 0 });
+    }
+}
+
+#[test]
+fn send_message_errors() {
+    let (mut senders, node0_recv) = crate::test::setup_node0();
+
+    // Messages without a route are queued, until the queue is full
+    for _ in 0..OutboundLimits::default().max_per_destination {
+        assert_eq!(
+            Server::<EchoServer>::send_message(0, &mut senders, 5, Message::ReqServerType, None),
+            Ok(())
+        );
+    }
+    assert_eq!(
+        Server::<EchoServer>::send_message(0, &mut senders, 5, Message::ReqServerType, None),
+        Err(ServerError::QueueFull(5))
+    );
+
+    drop(node0_recv);
+    assert_eq!(
+        Server::<EchoServer>::send_message(0, &mut senders, 0, Message::ReqServerType, None),
+        Err(ServerError::Disconnected(0))
+    );
+}
+
+/// Forward any messages that we receive to client 5
+struct ForwardServer {}
+
+impl ServerProtocol for ForwardServer {
+    fn on_message(
+        &mut self,
+        server: NodeId,
+        senders: &mut crate::server::ServerSenders,
+        _from: NodeId,
+        message: Message,
+        _session_id: u64,
+    ) {
+        assert_eq!(
+            Server::<ForwardServer>::send_message(server, senders, 5, message, None),
+            Ok(())
+        );
+    }
+}
+
+/// Answer any messages that we receive, and forward them to client 5 as a new message
+struct EchoForwardServer {}
+
+impl ServerProtocol for EchoForwardServer {
+    fn on_message(
+        &mut self,
+        server: NodeId,
+        senders: &mut crate::server::ServerSenders,
+        from: NodeId,
+        message: Message,
+        session_id: u64,
+    ) {
+        assert_eq!(
+            Server::<EchoForwardServer>::send_message(
+                server,
+                senders,
+                from,
+                message.clone(),
+                Some(session_id)
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            Server::<EchoForwardServer>::send_message(server, senders, 5, message, None),
+            Ok(())
+        );
+    }
+}
+
+#[test]
+fn new_session_skips_session_in_use() {
+    let (controller_send, _test_controller_recv) = unbounded::<LeafEvent>();
+    let (_test_controller_send, controller_recv) = unbounded::<LeafCommand>();
+    let (test_packet_send, packet_recv) = unbounded::<Packet>();
+    let mut packet_send = HashMap::<NodeId, Sender<Packet>>::new();
+
+    let (node1_send, node1_recv) = unbounded::<Packet>();
+    packet_send.insert(1, node1_send);
+
+    let mut server = Server::create(
+        0,
+        controller_send,
+        controller_recv,
+        packet_recv,
+        packet_send,
+        EchoForwardServer {},
+        ServerConfig::default(),
+    );
+    let fragments = Message::ReqServerType.into_fragments();
+    let mut request = |session_id: u64| {
+        assert!(test_packet_send
+            .send(Packet::new_fragment(
+                SourceRoutingHeader::with_first_hop(vec![5, 1, 0]),
+                session_id,
+                fragments[0].clone(),
+            ))
+            .is_ok());
+        server.poll();
+        node1_recv
+            .try_iter()
+            .filter(|p| matches!(p.pack_type, PacketType::MsgFragment(_)))
+            .map(|p| p.session_id)
+            .collect::<Vec<u64>>()
+    };
+
+    // Response uses the session of the request, the forwarded message a session chosen by the server
+    let sessions = request(777);
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(sessions[0], 777);
+    let started = sessions[1];
+
+    // Client 5 happens to use the next session the server would choose, which is still unacknowledged
+    assert_eq!(request(started + 1), vec![started + 1, started + 2]);
+}
+
+#[test]
+fn queued_until_route() {
+    let (controller_send, _test_controller_recv) = unbounded::<LeafEvent>();
+    let (_test_controller_send, controller_recv) = unbounded::<LeafCommand>();
+    let (test_packet_send, packet_recv) = unbounded::<Packet>();
+    let mut packet_send = HashMap::<NodeId, Sender<Packet>>::new();
+
+    let (node1_send, node1_recv) = unbounded::<Packet>();
+    packet_send.insert(1, node1_send);
+
+    let mut server = Server::create(
+        0,
+        controller_send,
+        controller_recv,
+        packet_recv,
+        packet_send,
+        ForwardServer {},
+        ServerConfig::default(),
+    );
+
+    // Client 3 sends a message for client 5, which is not known yet
+    let message = Message::ReqServerType;
+    let fragments = message.clone().into_fragments();
+    assert!(test_packet_send
+        .send(Packet::new_fragment(
+            SourceRoutingHeader::with_first_hop(vec![3, 1, 0]),
+            777,
+            fragments[0].clone(),
+        ))
+        .is_ok());
+    server.update();
+    assert!(node1_recv.recv_timeout(Duration::from_millis(10)).is_ok()); // Ack
+
+    // Message is queued, and a flood is started to find client 5
+    let flood_id = match node1_recv.recv_timeout(Duration::from_millis(10)) {
+        Ok(packet) => match packet.pack_type {
+            PacketType::FloodRequest(req) => req.flood_id,
+            _ => panic!("Packet is not a flood request."),
+        },
+        Err(e) => panic!("Did not receive packet (expected flood request): {}", e),
+    };
+    assert!(node1_recv.try_recv().is_err());
+
+    // Message is sent once the flood response arrives
+    assert!(test_packet_send
+        .send(Packet::new_flood_response(
+            SourceRoutingHeader::with_first_hop(vec![5, 1, 0]),
+            1,
+            FloodResponse {
+                flood_id,
+                path_trace: vec![
+                    (0, NodeType::Server),
+                    (1, NodeType::Drone),
+                    (5, NodeType::Client),
+                ],
+            },
+        ))
+        .is_ok());
+    server.update();
+    match node1_recv.recv_timeout(Duration::from_millis(10)) {
+        Ok(packet) => {
+            assert_eq!(packet.routing_header.hops, vec![0, 1, 5]);
+            assert_eq!(
+                panic_to_message_multi(vec![Ok::<Packet, String>(packet)]),
+                message
+            );
+        }
+        Err(e) => panic!("Did not receive packet (expected queued message): {}", e),
+    }
+}
+
+#[test]
+fn queued_after_recent_flood() {
+    let (controller_send, _test_controller_recv) = unbounded::<LeafEvent>();
+    let (_test_controller_send, controller_recv) = unbounded::<LeafCommand>();
+    let (test_packet_send, packet_recv) = unbounded::<Packet>();
+    let mut packet_send = HashMap::<NodeId, Sender<Packet>>::new();
+
+    let (node1_send, node1_recv) = unbounded::<Packet>();
+    packet_send.insert(1, node1_send);
+
+    let clock = VirtualClock::new();
+    let mut server = Server::create(
+        0,
+        controller_send,
+        controller_recv,
+        packet_recv,
+        packet_send,
+        ForwardServer {},
+        ServerConfig::default()
+            .with_min_flood_interval(Duration::from_millis(200))
+            .with_clock(Clock::Virtual(clock.clone())),
+    );
+    server.discover();
+    assert!(node1_recv
+        .try_recv()
+        .is_ok_and(|p| matches!(p.pack_type, PacketType::FloodRequest(_))));
+
+    // Message for the unknown client 5 arrives right after the flood
+    let fragments = Message::ReqServerType.into_fragments();
+    assert!(test_packet_send
+        .send(Packet::new_fragment(
+            SourceRoutingHeader::with_first_hop(vec![3, 1, 0]),
+            777,
+            fragments[0].clone(),
+        ))
+        .is_ok());
+    server.poll();
+    assert!(node1_recv
+        .try_recv()
+        .is_ok_and(|p| matches!(p.pack_type, PacketType::Ack(_))));
+    assert!(node1_recv.try_recv().is_err());
+    assert_eq!(server.next_timeout(), Duration::from_millis(200));
+
+    // Another flood is started once the interval has passed
+    clock.advance(Duration::from_millis(200));
+    server.poll();
+    assert!(node1_recv
+        .try_recv()
+        .is_ok_and(|p| matches!(p.pack_type, PacketType::FloodRequest(_))));
+}
+
+#[test]
+fn remove_sender_reroutes() {
+    let (mut server, test_controller_send, _test_packet_send, node1_recv, node2_recv) =
+        setup_two_routes();
+
+    // Drone 1 is disconnected before the response is acknowledged
+    assert!(test_controller_send
+        .send(LeafCommand::RemoveSender(1))
+        .is_ok());
+    server.update();
+
+    // Response is resent through drone 2
+    match node2_recv.recv_timeout(Duration::from_millis(10)) {
+        Ok(packet) => {
+            assert_eq!(packet.session_id, 777);
+            assert_eq!(packet.routing_header.hops, vec![0, 2, 5]);
+            assert!(matches!(packet.pack_type, PacketType::MsgFragment(_)));
+        }
+        Err(e) => panic!("Did not receive packet (expected resend): {}", e),
+    }
+    assert!(node1_recv.try_recv().is_err());
+
+    // New neighbor is flooded to discover the nodes behind it
+    let (node3_send, node3_recv) = unbounded::<Packet>();
+    assert!(test_controller_send
+        .send(LeafCommand::AddSender(3, node3_send))
+        .is_ok());
+    server.update();
+    match node3_recv.recv_timeout(Duration::from_millis(10)) {
+        Ok(packet) => assert!(matches!(packet.pack_type, PacketType::FloodRequest(_))),
+        Err(e) => panic!("Did not receive packet (expected flood request): {}", e),
+    }
+}
+
+/// Transport that keeps every packet sent through it
+#[derive(Clone, Default)]
+struct RecordingTransport {
+    packets: Arc<Mutex<Vec<Packet>>>,
+}
+
+impl PacketTransport for RecordingTransport {
+    fn send(&self, packet: Packet) -> Result<(), SendError<Packet>> {
+        match self.packets.lock() {
+            Ok(mut packets) => {
+                packets.push(packet);
+                Ok(())
+            }
+            Err(_) => Err(SendError(packet)),
+        }
+    }
+}
+
+#[test]
+fn custom_transport() {
+    let (controller_send, _test_controller_recv) = unbounded::<LeafEvent>();
+    let (_test_controller_send, controller_recv) = unbounded::<LeafCommand>();
+    let (test_packet_send, packet_recv) = unbounded::<Packet>();
+    let transport = RecordingTransport::default();
+    let packet_send = HashMap::from([(0, transport.clone())]);
+
+    let mut server = Server::create(
+        1,
+        controller_send,
+        controller_recv,
+        packet_recv,
+        packet_send,
+        EchoServer::new(),
+        ServerConfig::default(),
+    );
+
+    let message = Message::ReqServerType;
+    let fragments = message.clone().into_fragments();
+    assert_eq!(fragments.len(), 1);
+    assert!(test_packet_send
+        .send(Packet::new_fragment(
+            SourceRoutingHeader::with_first_hop(vec![0, 1]),
+            777,
+            fragments[0].clone(),
+        ))
+        .is_ok());
+    server.update();
+
+    // Ack and response are both sent through the transport
+    let packets = transport.packets.lock().expect("Transport lock poisoned.");
+    assert_eq!(packets.len(), 2);
+    assert_eq!(
+        packets[0].pack_type,
+        PacketType::Ack(Ack { fragment_index: 0 })
+    );
+    assert!(packets.iter().all(|p| p.session_id == 777));
+    assert_eq!(
+        panic_to_message(Ok::<_, String>(packets[1].clone())),
+        message
+    );
+}
+
+#[test]
+fn hostile_packets() {
+    let (controller_send, _test_controller_recv) = unbounded::<LeafEvent>();
+    let (_test_controller_send, controller_recv) = unbounded::<LeafCommand>();
+    let (test_packet_send, packet_recv) = unbounded::<Packet>();
+    let mut packet_send = HashMap::<NodeId, Sender<Packet>>::new();
+
+    let (node0_send, node0_recv) = unbounded::<Packet>();
+    packet_send.insert(0, node0_send);
+
+    let mut server = Server::create(
+        1,
+        controller_send,
+        controller_recv,
+        packet_recv,
+        packet_send,
+        EchoServer::new(),
+        ServerConfig::default(),
+    );
+
+    let fragment = |fragment_index, total_n_fragments, length| Fragment {
+        fragment_index,
+        total_n_fragments,
+        length,
+        data: [0xff; FRAGMENT_DSIZE],
+    };
+    let route = |hop_index, hops: &[NodeId]| SourceRoutingHeader {
+        hop_index,
+        hops: hops.to_vec(),
+    };
+    let ack = |routing_header, fragment_index| Packet {
+        routing_header,
+        session_id: 13,
+        pack_type: PacketType::Ack(Ack { fragment_index }),
+    };
+    let nack = |nack_type| Nack {
+        fragment_index: 0,
+        nack_type,
+    };
+
+    let hostile = vec![
+        // Malformed routes
+        Packet::new_fragment(route(0, &[]), 1, fragment(0, 1, 10)),
+        Packet::new_fragment(route(usize::MAX, &[0, 1]), 2, fragment(0, 1, 10)),
+        Packet::new_fragment(route(0, &[1]), 3, fragment(0, 1, 10)),
+        Packet::new_fragment(route(2, &[0, 1, 1, 1]), 4, fragment(0, 1, 10)),
+        Packet::new_fragment(route(1, &[0, 1, 9]), 5, fragment(0, 1, 10)),
+        Packet::new_fragment(route(7, &[5, 3, 1]), 6, fragment(0, 1, 10)),
+        // Malformed fragments
+        Packet::new_fragment(route(1, &[0, 1]), 7, fragment(5, 2, 10)),
+        Packet::new_fragment(route(1, &[0, 1]), 8, fragment(0, u64::MAX, 10)),
+        Packet::new_fragment(route(1, &[0, 1]), 9, fragment(u64::MAX, u64::MAX, 10)),
+        Packet::new_fragment(route(1, &[0, 1]), 10, fragment(0, 0, 10)),
+        Packet::new_fragment(route(1, &[0, 1]), 11, fragment(0, 1, u8::MAX)),
+        Packet::new_fragment(route(1, &[0, 1]), 12, fragment(0, 2, 0)),
+        Packet::new_fragment(route(1, &[0, 1]), 12, fragment(1, 3, 0)),
+        Packet::new_fragment(route(1, &[0, 1]), 12, fragment(1, 2, u8::MAX)),
+        // Acks and nacks for packets that were never sent
+        ack(route(0, &[]), 0),
+        ack(route(usize::MAX, &[0, 1]), u64::MAX),
+        Packet::new_nack(route(0, &[]), 14, nack(NackType::Dropped)),
+        Packet::new_nack(route(usize::MAX, &[3, 1]), 14, nack(NackType::Dropped)),
+        Packet::new_nack(route(1, &[3, 1]), 14, nack(NackType::DestinationIsDrone)),
+        Packet::new_nack(route(1, &[3, 1]), 14, nack(NackType::ErrorInRouting(1))),
+        Packet::new_nack(
+            route(1, &[3, 1]),
+            14,
+            nack(NackType::UnexpectedRecipient(3)),
+        ),
+        // Floods with malformed path traces
+        Packet::new_flood_request(
+            route(0, &[]),
+            15,
+            FloodRequest {
+                flood_id: 1,
+                initiator_id: 0,
+                path_trace: vec![],
+            },
+        ),
+        Packet::new_flood_request(
+            route(usize::MAX, &[]),
+            16,
+            FloodRequest {
+                flood_id: 2,
+                initiator_id: 1,
+                path_trace: vec![(1, NodeType::Server)],
+            },
+        ),
+        Packet::new_flood_request(
+            route(0, &[]),
+            17,
+            FloodRequest {
+                flood_id: 3,
+                initiator_id: 0,
+                path_trace: vec![(0, NodeType::Client), (1, NodeType::Drone)],
+            },
+        ),
+        Packet::new_flood_response(
+            route(0, &[]),
+            18,
+            FloodResponse {
+                flood_id: 4,
+                path_trace: vec![],
+            },
+        ),
+        Packet::new_flood_response(
+            route(usize::MAX, &[3, 1]),
+            19,
+            FloodResponse {
+                flood_id: 5,
+                path_trace: vec![
+                    (1, NodeType::Server),
+                    (1, NodeType::Drone),
+                    (0, NodeType::Client),
+                ],
+            },
+        ),
+    ];
+    for packet in hostile {
+        assert!(test_packet_send.send(packet).is_ok());
+        server.poll();
+    }
+    // Acks, nacks and flood responses to the hostile packets
+    node0_recv.try_iter().for_each(drop);
+
+    // Nack for a fragment meant for another node goes back the way the fragment came
+    assert!(test_packet_send
+        .send(Packet::new_fragment(
+            route(1, &[0, 1, 9]),
+            5,
+            fragment(0, 1, 10)
+        ))
+        .is_ok());
+    server.poll();
+    match node0_recv.try_recv() {
+        Ok(packet) => {
+            assert_eq!(packet.routing_header.hops, vec![1, 0]);
+            assert!(matches!(
+                packet.pack_type,
+                PacketType::Nack(Nack {
+                    nack_type: NackType::UnexpectedRecipient(1),
+                    ..
+                })
+            ));
+        }
+        Err(e) => panic!("Did not receive nack: {}", e),
+    }
+
+    // Server still handles a valid request
+    let message = Message::ReqServerType;
+    let fragments = message.clone().into_fragments();
+    assert!(test_packet_send
+        .send(Packet::new_fragment(
+            SourceRoutingHeader::with_first_hop(vec![0, 1]),
+            777,
+            fragments[0].clone(),
+        ))
+        .is_ok());
+    server.poll();
+
+    match node0_recv.try_recv() {
+        Ok(packet) => assert_eq!(packet.pack_type, PacketType::Ack(Ack { fragment_index: 0 })),
+        Err(e) => panic!("Did not receive ack: {}", e),
+    }
+    assert_eq!(panic_to_message(node0_recv.try_recv()), message);
+}
+
+#[test]
+fn config_zero_values() {
+    assert!(ServerConfig::default().validate().is_ok());
+
+    let mut config = ServerConfig::default()
+        .with_tick_interval(Duration::ZERO)
+        .with_retransmit_policy(RetransmitPolicy {
+            ack_timeout: Duration::ZERO,
+            backoff: 0,
+            max_retries: 5,
+        });
+    assert_eq!(config.validate(), Err(ConfigError::Zero("tick_interval")));
+
+    // Values the server cannot run with are replaced by their defaults
+    assert_eq!(
+        config.sanitize(),
+        vec![
+            ConfigError::Zero("tick_interval"),
+            ConfigError::Zero("retransmit.ack_timeout"),
+            ConfigError::Zero("retransmit.backoff"),
+        ]
+    );
+    assert!(config.validate().is_ok());
+    assert_eq!(config.tick_interval, ServerConfig::default().tick_interval);
+    assert_eq!(config.retransmit.max_retries, 5);
+}
@@ -0,0 +1,124 @@
+#![cfg(test)]
+// Testing of the server in a simulated network
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use common_structs::message::{FileWithData, Message, ServerType};
+
+use crate::server::ServerConfig;
+use crate::sim::{DroneConfig, Simulation, SimulationBuilder};
+use crate::text::TextServer;
+
+const SERVER: u8 = 100;
+const CLIENT: u8 = 10;
+
+fn text_server() -> TextServer {
+    let mut file_map = HashMap::new();
+    file_map.insert(
+        String::from("long"),
+        FileWithData {
+            file: "Hello, World! ".repeat(200),
+            related_data: HashMap::new(),
+        },
+    );
+    TextServer::new(file_map)
+}
+
+/// Client - 1 - server, with a longer detour client - 2 - 3 - server
+fn network(seed: u64, drop_rate: f64) -> Simulation<TextServer> {
+    let drone = DroneConfig {
+        drop_rate,
+        ..DroneConfig::default()
+    };
+    SimulationBuilder::new(seed)
+        .drone(1, drone.clone())
+        .drone(2, drone.clone())
+        .drone(3, drone)
+        .client(CLIENT)
+        .link(CLIENT, 1)
+        .link(1, SERVER)
+        .link(CLIENT, 2)
+        .link(2, 3)
+        .link(3, SERVER)
+        .build(SERVER, text_server(), ServerConfig::default())
+}
+
+fn assert_long_file(sim: &Simulation<TextServer>) {
+    match sim.messages(CLIENT) {
+        [(from, Message::RespFile(file))] => {
+            assert_eq!(*from, SERVER);
+            assert_eq!(file.file, "Hello, World! ".repeat(200));
+        }
+        messages => panic!(
+            "Client received {} messages instead of the file.",
+            messages.len()
+        ),
+    }
+}
+
+#[test]
+fn request_response() {
+    let mut sim = network(0, 0.0);
+    sim.run_for(Duration::from_millis(50));
+
+    assert!(sim.request(CLIENT, Message::ReqServerType).is_some());
+    sim.run_for(Duration::from_millis(50));
+
+    match sim.messages(CLIENT) {
+        [(SERVER, Message::RespServerType(ServerType::Text(_)))] => {}
+        messages => panic!("Client received {} unexpected messages.", messages.len()),
+    }
+    assert_eq!(sim.server().stats().resends, 0);
+}
+
+#[test]
+fn resend_dropped() {
+    let mut sim = network(7, 0.0);
+    sim.run_for(Duration::from_millis(50));
+
+    sim.request(CLIENT, Message::ReqFile(String::from("long")));
+    sim.run_for(Duration::from_millis(2));
+    // The simulated client does not resend its request, so only drop the response
+    assert!(!sim.server().stats().messages_received.is_empty());
+    for drone in 1..=3 {
+        sim.set_drop_rate(drone, 0.3);
+    }
+    sim.run_for(Duration::from_secs(10));
+
+    assert_long_file(&sim);
+    assert!(sim.dropped(1) + sim.dropped(2) + sim.dropped(3) > 0);
+    assert!(sim.server().stats().resends > 0);
+}
+
+#[test]
+fn reroute_after_crash() {
+    let mut sim = network(0, 0.0);
+    sim.run_for(Duration::from_millis(50));
+
+    sim.crash(1);
+    sim.request(CLIENT, Message::ReqFile(String::from("long")));
+    sim.run_for(Duration::from_secs(5));
+
+    assert_long_file(&sim);
+}
+
+#[test]
+fn same_seed_same_outcome() {
+    let outcome = |seed| {
+        let mut sim = network(seed, 0.2);
+        sim.run_for(Duration::from_millis(50));
+        sim.request(CLIENT, Message::ReqFile(String::from("long")));
+        sim.run_for(Duration::from_secs(10));
+        (
+            sim.messages(CLIENT).len(),
+            sim.dropped(1),
+            sim.dropped(2),
+            sim.dropped(3),
+            sim.server().stats().resends,
+            sim.server().stats().fragments_sent,
+        )
+    };
+
+    assert_eq!(outcome(42), outcome(42));
+}
@@ -1,11 +1,14 @@
 #![cfg(test)]
 // Testing of the text protocol implementation
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
 
 use common_structs::message::{FileWithData, Message, ServerType};
 
-use crate::text::TextServer;
+use crate::media::media_uuid;
+use crate::text::{default_files, load_files, TextServer};
 
 use super::{test_on_message, test_on_message_fn};
 
@@ -73,3 +76,102 @@ fn media_not_found() {
     let mut server = TextServer::new(HashMap::new());
     test_on_message(&mut server, Message::ReqFile(id), Message::ErrNotFound);
 }
+
+/// Empty directory for a test, unique per test and test run
+fn content_dir(name: &str) -> PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("rusty-drones-text-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("Could not create content directory");
+    dir
+}
+
+#[test]
+fn load_tree() {
+    let dir = content_dir("tree");
+    fs::create_dir_all(dir.join("notes/deep")).expect("Could not create directories");
+    fs::write(dir.join("hello.txt"), "Hello, World!").expect("Could not write file");
+    fs::write(
+        dir.join("notes/plop.md"),
+        "# Plop\n![Chicken](chicken.jpeg)\n![Web](https://example.com/a.png)\n![Titled](img/a.png \"Title\")",
+    )
+    .expect("Could not write file");
+    fs::write(
+        dir.join("notes/plop.md.meta.toml"),
+        "media = [\"video.mp4\"]",
+    )
+    .expect("Could not write file");
+    fs::write(dir.join("notes/deep/todo.txt"), "Nothing").expect("Could not write file");
+    fs::write(dir.join(".hidden"), "Not content").expect("Could not write file");
+
+    let loaded = load_files(&dir).expect("Could not load directory");
+    let _ = fs::remove_dir_all(&dir);
+
+    assert!(loaded.errors.is_empty(), "{:?}", loaded.errors);
+    let links: HashSet<&str> = loaded.files.keys().map(|link| link.as_str()).collect();
+    assert_eq!(
+        links,
+        HashSet::from(["hello.txt", "notes/plop.md", "notes/deep/todo.txt"])
+    );
+    assert!(loaded.files["hello.txt"].related_data.is_empty());
+    assert_eq!(
+        loaded.files["notes/plop.md"].related_data,
+        HashMap::from([
+            (String::from("chicken.jpeg"), media_uuid()),
+            (String::from("img/a.png"), media_uuid()),
+            (String::from("video.mp4"), media_uuid()),
+        ])
+    );
+}
+
+#[test]
+fn load_errors() {
+    let dir = content_dir("errors");
+    fs::write(dir.join("binary.txt"), [0xff, 0xfe, 0x00]).expect("Could not write file");
+    fs::write(dir.join("orphan.md.meta.toml"), "media = []").expect("Could not write file");
+    fs::write(dir.join("broken.md"), "Broken metadata").expect("Could not write file");
+    fs::write(dir.join("broken.md.meta.toml"), "media = 5").expect("Could not write file");
+    fs::write(dir.join("other.md"), "Other server").expect("Could not write file");
+    fs::write(
+        dir.join("other.md.meta.toml"),
+        "server = 7\nmedia = [\"video.mp4\"]",
+    )
+    .expect("Could not write file");
+
+    let loaded = load_files(&dir).expect("Could not load directory");
+    let _ = fs::remove_dir_all(&dir);
+
+    // Every problem is reported, the other files are still loaded
+    let mut failed: Vec<String> = loaded
+        .errors
+        .iter()
+        .filter_map(|error| error.path.file_name())
+        .map(|name| name.to_string_lossy().into_owned())
+        .collect();
+    failed.sort();
+    assert_eq!(
+        failed,
+        vec!["binary.txt", "broken.md.meta.toml", "orphan.md.meta.toml"]
+    );
+    assert_eq!(loaded.files.len(), 2);
+    assert!(loaded.files["broken.md"].related_data.is_empty());
+    assert_eq!(
+        loaded.files["other.md"].related_data,
+        HashMap::from([(String::from("video.mp4"), 7)])
+    );
+}
+
+#[test]
+fn load_missing_dir() {
+    let dir = content_dir("missing");
+    let _ = fs::remove_dir_all(&dir);
+    assert!(load_files(&dir).is_err());
+}
+
+#[test]
+fn default_related() {
+    assert_eq!(
+        default_files()["plophub"].related_data,
+        HashMap::from([(String::from("chicken.jpeg"), media_uuid())])
+    );
+}
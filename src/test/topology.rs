@@ -0,0 +1,159 @@
+#![cfg(test)]
+// Testing of the topology graph used for routing
+
+use wg_2024::packet::NodeType;
+
+use crate::server::{RoutingStrategy, Topology};
+
+/// Server 0 connected to drones 1 and 2, client 5 behind drone 1 (through drone 3) and behind drone 2
+fn setup_topology() -> Topology {
+    let mut topology = Topology::new(0);
+    topology.add_path_trace(vec![
+        (0, NodeType::Server),
+        (1, NodeType::Drone),
+        (3, NodeType::Drone),
+        (5, NodeType::Client),
+    ]);
+    topology.add_path_trace(vec![
+        (0, NodeType::Server),
+        (2, NodeType::Drone),
+        (5, NodeType::Client),
+    ]);
+    topology
+}
+
+#[test]
+fn shortest_route() {
+    let topology = setup_topology();
+    let route = topology
+        .route_to(5)
+        .expect("Route to client should be known");
+    assert_eq!(route.hops, vec![0, 2, 5]);
+    assert_eq!(route.current_hop(), Some(2));
+}
+
+#[test]
+fn remove_edge() {
+    let mut topology = setup_topology();
+    let version = topology.version();
+    topology.remove_edge(2, 5);
+    assert_ne!(topology.version(), version);
+
+    let route = topology
+        .route_to(5)
+        .expect("Route to client should be known");
+    assert_eq!(route.hops, vec![0, 1, 3, 5]);
+
+    topology.remove_edge(3, 5);
+    assert!(topology.route_to(5).is_none());
+}
+
+#[test]
+fn no_route_through_clients() {
+    let mut topology = Topology::new(0);
+    topology.add_path_trace(vec![
+        (0, NodeType::Server),
+        (1, NodeType::Drone),
+        (5, NodeType::Client),
+        (6, NodeType::Drone),
+        (7, NodeType::Client),
+    ]);
+    assert!(topology.route_to(7).is_none());
+    assert!(topology.route_to(1).is_none()); // Drones cannot be a destination
+}
+
+#[test]
+fn received_route() {
+    let mut topology = Topology::new(0);
+    topology.add_route(&[5, 3, 1, 0]);
+    let route = topology
+        .route_to(5)
+        .expect("Route to client should be known");
+    assert_eq!(route.hops, vec![0, 1, 3, 5]);
+
+    let version = topology.version();
+    topology.add_route(&[5, 3, 1, 0]);
+    assert_eq!(topology.version(), version);
+}
+
+#[test]
+fn avoid_dropping_drone() {
+    let mut topology = setup_topology();
+    for _ in 0..10 {
+        topology.record_dropped(2);
+    }
+
+    // Longer route through reliable drones is preferred
+    for _ in 0..100 {
+        topology.record_forwarded(&[0, 1, 3, 5]);
+    }
+    let route = topology
+        .route_to(5)
+        .expect("Route to client should be known");
+    assert_eq!(route.hops, vec![0, 1, 3, 5]);
+}
+
+#[test]
+fn equal_reliability_fewest_hops() {
+    let mut topology = setup_topology();
+    topology.add_path_trace(vec![
+        (0, NodeType::Server),
+        (4, NodeType::Drone),
+        (5, NodeType::Client),
+    ]);
+
+    // Drones 2 and 4 are equally reliable, lowest id is used
+    let route = topology
+        .route_to(5)
+        .expect("Route to client should be known");
+    assert_eq!(route.hops, vec![0, 2, 5]);
+
+    topology.record_forwarded(&[0, 4, 5]);
+    let route = topology
+        .route_to(5)
+        .expect("Route to client should be known");
+    assert_eq!(route.hops, vec![0, 4, 5]);
+}
+
+#[test]
+fn fewest_hops_strategy() {
+    let mut topology = setup_topology();
+    topology.set_strategy(RoutingStrategy::FewestHops);
+    for _ in 0..10 {
+        topology.record_dropped(2);
+    }
+
+    // Drop rates are ignored
+    let route = topology
+        .route_to(5)
+        .expect("Route to client should be known");
+    assert_eq!(route.hops, vec![0, 2, 5]);
+}
+
+#[test]
+fn no_route_to_self() {
+    // Routes never end at ourselves and never contain a link from a node to itself
+    let mut topology = Topology::new(0);
+    topology.add_route(&[0, 0]);
+    assert!(topology.route_to(0).is_none());
+
+    topology.add_route(&[5, 0]);
+    assert_eq!(
+        topology.route_to(5).map(|route| route.hops),
+        Some(vec![0, 5])
+    );
+}
+
+#[test]
+fn small_drop_rate_change_keeps_routes() {
+    let mut topology = setup_topology();
+
+    // An ack barely changes the estimate of drone 2
+    let version = topology.version();
+    topology.record_forwarded(&[0, 2, 5]);
+    assert_eq!(topology.version(), version);
+
+    // A few drops change it enough to look for better routes
+    topology.record_dropped(2);
+    assert_ne!(topology.version(), version);
+}
@@ -0,0 +1,43 @@
+#![cfg(test)]
+// Testing of the transports to neighbors outside of the process
+
+use std::net::UdpSocket;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crossbeam_channel::unbounded;
+use wg_2024::network::SourceRoutingHeader;
+use wg_2024::packet::{Ack, Packet, PacketType};
+
+use crate::server::{spawn_udp_receiver, PacketTransport, UdpTransport};
+
+#[test]
+fn udp_round_trip() {
+    let sender_socket =
+        Arc::new(UdpSocket::bind("127.0.0.1:0").expect("Could not bind sender socket."));
+    let receiver_socket =
+        Arc::new(UdpSocket::bind("127.0.0.1:0").expect("Could not bind receiver socket."));
+    let receiver_address = receiver_socket
+        .local_addr()
+        .expect("Receiver socket has no address.");
+
+    let (packet_send, packet_recv) = unbounded::<Packet>();
+    spawn_udp_receiver(receiver_socket, packet_send);
+
+    let transport = UdpTransport::new(sender_socket, receiver_address);
+    let packet = Packet {
+        routing_header: SourceRoutingHeader::with_first_hop(vec![0, 1]),
+        session_id: 777,
+        pack_type: PacketType::Ack(Ack { fragment_index: 3 }),
+    };
+    assert!(transport.send(packet.clone()).is_ok());
+
+    match packet_recv.recv_timeout(Duration::from_secs(1)) {
+        Ok(received) => {
+            assert_eq!(received.session_id, 777);
+            assert_eq!(received.routing_header.hops, vec![0, 1]);
+            assert_eq!(received.pack_type, packet.pack_type);
+        }
+        Err(e) => panic!("Did not receive packet: {}", e),
+    }
+}
@@ -1,6 +1,10 @@
 use std::{
     collections::HashMap,
+    fmt::{Display, Formatter},
+    fs,
     hash::{DefaultHasher, Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
 };
 
 use common_structs::{
@@ -8,9 +12,17 @@ use common_structs::{
     message::{FileWithData, Link, Message, ServerType},
 };
 use crossbeam_channel::{Receiver, Sender};
+use log::warn;
+use serde::Deserialize;
 use wg_2024::{network::NodeId, packet::Packet};
 
-use crate::server::{Server, ServerProtocol, ServerSenders};
+use crate::media::media_uuid;
+use crate::server::{
+    ConcurrentProtocol, Reply, Server, ServerConfig, ServerProtocol, ServerSenders, WorkerPool,
+};
+
+/// Amount of threads handling requests when running on a worker pool
+const WORKER_COUNT: usize = 4;
 
 pub struct TextServer {
     uuid: u64,
@@ -26,6 +38,33 @@ impl TextServer {
     }
 }
 
+impl ConcurrentProtocol for TextServer {
+    fn handle(
+        &self,
+        _server: NodeId,
+        from: NodeId,
+        message: Message,
+        session_id: u64,
+    ) -> Vec<Reply> {
+        let response = match message {
+            Message::ReqServerType => Message::RespServerType(ServerType::Text(self.uuid)),
+            // List files present in this server
+            Message::ReqFilesList => {
+                Message::RespFilesList(self.file_map.keys().cloned().collect())
+            }
+            Message::ReqFile(id) => match self.file_map.get(&id) {
+                // File is present in this server
+                Some(file) => Message::RespFile(file.clone()),
+                // File with that id is not known
+                None => Message::ErrNotFound,
+            },
+            // Default response
+            _ => Message::ErrUnsupportedRequestType,
+        };
+        vec![Reply::response(from, response, session_id)]
+    }
+}
+
 impl ServerProtocol for TextServer {
     fn on_message(
         &mut self,
@@ -35,58 +74,206 @@ impl ServerProtocol for TextServer {
         message: Message,
         session_id: u64,
     ) {
-        match message {
-            Message::ReqServerType => {
-                Server::<TextServer>::send_message(
-                    server,
-                    senders,
-                    from,
-                    Message::RespServerType(ServerType::Text(self.uuid)),
-                    Some(session_id),
-                );
+        for reply in self.handle(server, from, message, session_id) {
+            if let Err(e) = Server::<TextServer>::send_message(
+                server,
+                senders,
+                reply.to,
+                reply.message,
+                reply.session_id,
+            ) {
+                warn!("WARNING: Could not send response to {}. {}", reply.to, e);
             }
-            Message::ReqFilesList => {
-                // List files present in this server
-                Server::<TextServer>::send_message(
-                    server,
-                    senders,
-                    from,
-                    Message::RespFilesList(self.file_map.keys().cloned().collect()),
-                    Some(session_id),
-                );
+        }
+    }
+}
+
+/// Files available in the network
+pub fn default_files() -> HashMap<Link, FileWithData> {
+    let mut file_map = HashMap::new();
+    file_map.insert(
+        String::from("helloworld"),
+        FileWithData {
+            file: String::from("Hello, World!"),
+            related_data: HashMap::new(),
+        },
+    );
+
+    let file = String::from("# Plopmenz\n![Profile Picture](chicken.jpeg)");
+    file_map.insert(
+        String::from("plophub"),
+        FileWithData {
+            related_data: parse_related(&file),
+            file,
+        },
+    );
+
+    file_map
+}
+
+/// Suffix of the metadata file next to a text file, e.g. `notes/todo.md.meta.toml` for `notes/todo.md`
+pub const SIDECAR_SUFFIX: &str = ".meta.toml";
+
+/// Metadata of a text file, listing media the content refers to in some other way than an image
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Sidecar {
+    /// Uuid of the media server holding the media, by default the one of our media servers
+    server: Option<u64>,
+    #[serde(default)]
+    media: Vec<Link>,
+}
+
+/// File or directory that could not be loaded
+#[derive(Debug)]
+pub struct LoadError {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.reason)
+    }
+}
+
+/// Files loaded from a directory tree, together with everything that was skipped
+#[derive(Debug, Default)]
+pub struct LoadedFiles {
+    pub files: HashMap<Link, FileWithData>,
+    pub errors: Vec<LoadError>,
+}
+
+/// Files in a directory tree, each file is available by its path relative to the directory (e.g. `notes/todo.md`)
+/// Related media are the images in the content, plus the media listed in the sidecar metadata of the file
+/// Files that cannot be loaded are reported and skipped, only an unreadable directory is an error
+pub fn load_files(dir: &Path) -> io::Result<LoadedFiles> {
+    let mut loaded = LoadedFiles::default();
+    let mut sidecars = Vec::new();
+    let mut dirs = vec![read_sorted(dir)?];
+
+    while let Some(entries) = dirs.pop() {
+        for path in entries {
+            let name = path.file_name().and_then(|name| name.to_str());
+            if name.is_some_and(|name| name.starts_with('.')) {
+                // Hidden files (like .git) are not content
+                continue;
+            }
+
+            // Symbolic links are only followed to files, to never walk in circles
+            let is_dir = match fs::symlink_metadata(&path) {
+                Ok(metadata) => metadata.is_dir(),
+                Err(e) => {
+                    loaded.error(&path, e.to_string());
+                    continue;
+                }
+            };
+            if is_dir {
+                match read_sorted(&path) {
+                    Ok(entries) => dirs.push(entries),
+                    Err(e) => loaded.error(&path, e.to_string()),
+                }
+                continue;
+            }
+
+            let Some(link) = relative_link(dir, &path) else {
+                loaded.error(&path, String::from("Path is not valid UTF-8"));
+                continue;
+            };
+            if link.ends_with(SIDECAR_SUFFIX) {
+                sidecars.push((path, link));
+                continue;
             }
-            Message::ReqFile(id) => {
-                match self.file_map.get(&id) {
-                    // File is present in this server
-                    Some(file) => Server::<TextServer>::send_message(
-                        server,
-                        senders,
-                        from,
-                        Message::RespFile(file.clone()),
-                        Some(session_id),
-                    ),
-                    // File with that id is not known
-                    None => Server::<TextServer>::send_message(
-                        server,
-                        senders,
-                        from,
-                        Message::ErrNotFound,
-                        Some(session_id),
-                    ),
-                };
+
+            match fs::read_to_string(&path) {
+                Ok(file) => {
+                    let related_data = parse_related(&file);
+                    loaded
+                        .files
+                        .insert(link, FileWithData { file, related_data });
+                }
+                Err(e) => loaded.error(&path, e.to_string()),
             }
-            _ => {
-                // Default response
-                Server::<TextServer>::send_message(
-                    server,
-                    senders,
-                    from,
-                    Message::ErrUnsupportedRequestType,
-                    Some(session_id),
-                );
+        }
+    }
+
+    for (path, link) in sidecars {
+        let file_link = &link[..link.len() - SIDECAR_SUFFIX.len()];
+        let sidecar = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|sidecar| toml::from_str::<Sidecar>(&sidecar).map_err(|e| e.to_string()));
+
+        match (sidecar, loaded.files.get_mut(file_link)) {
+            (Ok(sidecar), Some(file)) => {
+                let server = sidecar.server.unwrap_or_else(media_uuid);
+                for media in sidecar.media {
+                    file.related_data.insert(media, server);
+                }
             }
+            (Ok(_), None) => loaded.error(&path, format!("No file {} to describe", file_link)),
+            (Err(e), _) => loaded.error(&path, e),
         }
     }
+
+    for error in loaded.errors.iter() {
+        warn!("WARNING: Skipping {}.", error);
+    }
+    Ok(loaded)
+}
+
+impl LoadedFiles {
+    fn error(&mut self, path: &Path, reason: String) {
+        self.errors.push(LoadError {
+            path: path.to_path_buf(),
+            reason,
+        });
+    }
+}
+
+/// Paths in a directory, in a fixed order to report errors the same way every time
+fn read_sorted(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<PathBuf>>>()?;
+    // Reversed, directories are visited from a stack
+    paths.sort_by(|a, b| b.cmp(a));
+    Ok(paths)
+}
+
+/// Link of a file, its path relative to the content directory with `/` between the parts
+fn relative_link(dir: &Path, path: &Path) -> Option<Link> {
+    let parts = path
+        .strip_prefix(dir)
+        .ok()?
+        .components()
+        .map(|part| part.as_os_str().to_str())
+        .collect::<Option<Vec<&str>>>()?;
+    Some(parts.join("/"))
+}
+
+/// Media referred to by the images in a (markdown) text, e.g. `![Profile Picture](chicken.jpeg)`
+/// Images on the web are not part of the network and are left out
+pub fn parse_related(file: &str) -> HashMap<Link, u64> {
+    let mut related_data = HashMap::new();
+    let mut rest = file;
+    while let Some(start) = rest.find("![") {
+        rest = &rest[start + 2..];
+        let Some(target_start) = rest.find("](") else {
+            break;
+        };
+        rest = &rest[target_start + 2..];
+        let Some(target_end) = rest.find(')') else {
+            break;
+        };
+
+        // Skip an optional title, as in ![alt](link "title")
+        let target = rest[..target_end].split_whitespace().next().unwrap_or("");
+        if !target.is_empty() && !target.contains("://") {
+            related_data.insert(String::from(target), media_uuid());
+        }
+        rest = &rest[target_end + 1..];
+    }
+    related_data
 }
 
 impl Leaf for Server<TextServer> {
@@ -100,38 +287,41 @@ impl Leaf for Server<TextServer> {
     where
         Self: Sized,
     {
-        // Files available in the network
-        let mut file_map = HashMap::new();
-        file_map.insert(
-            String::from("helloworld"),
-            FileWithData {
-                file: String::from("Hello, World!"),
-                related_data: HashMap::new(),
-            },
-        );
-
-        {
-            let mut s = DefaultHasher::new();
-            "SamuelMediaServer".hash(&mut s);
-            let media_uuid = s.finish();
-            let mut related_data = HashMap::new();
-            related_data.insert(String::from("chicken.jpeg"), media_uuid);
-            file_map.insert(
-                String::from("plophub"),
-                FileWithData {
-                    file: String::from("# Plopmenz\n![Profile Picture](chicken.jpeg)"),
-                    related_data,
-                },
-            );
-        }
+        Server::create(
+            id,
+            controller_send,
+            controller_recv,
+            packet_recv,
+            packet_send,
+            TextServer::new(default_files()),
+            ServerConfig::default(),
+        )
+    }
+
+    fn run(&mut self) {
+        self.run();
+    }
+}
 
+impl Leaf for Server<WorkerPool<TextServer>> {
+    fn new(
+        id: NodeId,
+        controller_send: Sender<LeafEvent>,
+        controller_recv: Receiver<LeafCommand>,
+        packet_recv: Receiver<Packet>,
+        packet_send: HashMap<NodeId, Sender<Packet>>,
+    ) -> Self
+    where
+        Self: Sized,
+    {
         Server::create(
             id,
             controller_send,
             controller_recv,
             packet_recv,
             packet_send,
-            TextServer::new(file_map),
+            WorkerPool::new(TextServer::new(default_files()), WORKER_COUNT),
+            ServerConfig::default(),
         )
     }
 